@@ -0,0 +1,27 @@
+-- This file should undo anything in `up.sql`
+--
+-- Only the most recently created token for each user survives.
+CREATE TABLE auth_tokens_old (
+   id INTEGER PRIMARY KEY NOT NULL,
+   user_id INTEGER UNIQUE NOT NULL,
+   "timestamp" DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+   key TEXT NOT NULL UNIQUE,
+   FOREIGN KEY (user_id) REFERENCES users(id)
+);
+
+INSERT INTO auth_tokens_old (id, user_id, "timestamp", key)
+SELECT id, user_id, "timestamp", key FROM auth_tokens
+WHERE id IN (SELECT MAX(id) FROM auth_tokens GROUP BY user_id);
+
+DROP INDEX IF EXISTS auth_tokens_key_index;
+DROP INDEX IF EXISTS auth_tokens_user_index;
+DROP TABLE auth_tokens;
+ALTER TABLE auth_tokens_old RENAME TO auth_tokens;
+
+CREATE UNIQUE INDEX auth_tokens_key_index ON auth_tokens (
+   key
+);
+
+CREATE UNIQUE INDEX auth_tokens_user_index ON auth_tokens (
+   user_id
+);
@@ -0,0 +1,30 @@
+-- Your SQL goes here
+--
+-- SQLite can't drop the UNIQUE constraint on `user_id` in place,
+-- so we rebuild the table and copy the existing tokens across.
+CREATE TABLE auth_tokens_new (
+   id INTEGER PRIMARY KEY NOT NULL,
+   user_id INTEGER NOT NULL,
+   "timestamp" DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+   key TEXT NOT NULL UNIQUE,
+   device_name TEXT NOT NULL DEFAULT '',
+   user_agent TEXT,
+   last_used DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+   FOREIGN KEY (user_id) REFERENCES users(id)
+);
+
+INSERT INTO auth_tokens_new (id, user_id, "timestamp", key, last_used)
+SELECT id, user_id, "timestamp", key, "timestamp" FROM auth_tokens;
+
+DROP INDEX IF EXISTS auth_tokens_key_index;
+DROP INDEX IF EXISTS auth_tokens_user_index;
+DROP TABLE auth_tokens;
+ALTER TABLE auth_tokens_new RENAME TO auth_tokens;
+
+CREATE UNIQUE INDEX auth_tokens_key_index ON auth_tokens (
+   key
+);
+
+CREATE INDEX auth_tokens_user_index ON auth_tokens (
+   user_id
+);
@@ -0,0 +1,26 @@
+-- This file should undo anything in `up.sql`
+--
+-- The plaintext keys can't be recovered, so every token is invalidated again.
+CREATE TABLE auth_tokens_old (
+   id INTEGER PRIMARY KEY NOT NULL,
+   user_id INTEGER NOT NULL,
+   "timestamp" DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+   key TEXT NOT NULL UNIQUE,
+   device_name TEXT NOT NULL DEFAULT '',
+   user_agent TEXT,
+   last_used DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+   FOREIGN KEY (user_id) REFERENCES users(id)
+);
+
+DROP INDEX IF EXISTS auth_tokens_selector_index;
+DROP INDEX IF EXISTS auth_tokens_user_index;
+DROP TABLE auth_tokens;
+ALTER TABLE auth_tokens_old RENAME TO auth_tokens;
+
+CREATE UNIQUE INDEX auth_tokens_key_index ON auth_tokens (
+   key
+);
+
+CREATE INDEX auth_tokens_user_index ON auth_tokens (
+   user_id
+);
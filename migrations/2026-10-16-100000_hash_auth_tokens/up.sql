@@ -0,0 +1,31 @@
+-- Your SQL goes here
+--
+-- Tokens are now presented as `{selector}.{secret}`. Only the selector is
+-- stored as-is; the secret is stored as its SHA-256 digest in `verifier`.
+--
+-- We can't hash the existing plaintext keys from SQL, so every existing
+-- token is invalidated; users will need to sign in again.
+CREATE TABLE auth_tokens_new (
+   id INTEGER PRIMARY KEY NOT NULL,
+   user_id INTEGER NOT NULL,
+   "timestamp" DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+   selector TEXT NOT NULL UNIQUE,
+   verifier BLOB NOT NULL,
+   device_name TEXT NOT NULL DEFAULT '',
+   user_agent TEXT,
+   last_used DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+   FOREIGN KEY (user_id) REFERENCES users(id)
+);
+
+DROP INDEX IF EXISTS auth_tokens_key_index;
+DROP INDEX IF EXISTS auth_tokens_user_index;
+DROP TABLE auth_tokens;
+ALTER TABLE auth_tokens_new RENAME TO auth_tokens;
+
+CREATE UNIQUE INDEX auth_tokens_selector_index ON auth_tokens (
+   selector
+);
+
+CREATE INDEX auth_tokens_user_index ON auth_tokens (
+   user_id
+);
@@ -0,0 +1,21 @@
+-- This file should undo anything in `up.sql`
+--
+-- SQLite can't drop columns, so we rebuild the table without it.
+CREATE TABLE users_old (
+   id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+   username TEXT UNIQUE NOT NULL,
+   password TEXT NOT NULL,
+   real_name TEXT NOT NULL DEFAULT '',
+   blurb TEXT NOT NULL DEFAULT ''
+);
+
+INSERT INTO users_old (id, username, password, real_name, blurb)
+SELECT id, username, password, real_name, blurb FROM users;
+
+DROP INDEX IF EXISTS users_username_index;
+DROP TABLE users;
+ALTER TABLE users_old RENAME TO users;
+
+CREATE UNIQUE INDEX users_username_index ON users (
+   username
+);
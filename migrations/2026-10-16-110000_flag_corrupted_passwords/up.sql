@@ -0,0 +1,11 @@
+-- Your SQL goes here
+ALTER TABLE users ADD COLUMN password_reset_required BOOLEAN NOT NULL DEFAULT 0;
+
+-- Legacy `$argon2${salt}${hash}$` passwords were written with each hash byte
+-- formatted as `{:x}`, which drops the leading zero of any byte below 0x10.
+-- An intact legacy password has a 128-character salt and exactly 64 hex digits
+-- of hash; anything shorter lost digits, and can never be validated again.
+-- Their owners must reset their passwords before they can sign in.
+UPDATE users SET password_reset_required = 1
+WHERE password LIKE '$argon2$%'
+   AND length(password) != length('$argon2$') + 128 + 1 + 64 + 1;
@@ -0,0 +1,23 @@
+-- This file should undo anything in `up.sql`
+--
+-- SQLite can't drop columns, so we rebuild the table without it.
+CREATE TABLE users_old (
+   id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+   username TEXT UNIQUE NOT NULL,
+   password TEXT NOT NULL,
+   real_name TEXT NOT NULL DEFAULT '',
+   blurb TEXT NOT NULL DEFAULT '',
+   password_reset_required BOOLEAN NOT NULL DEFAULT 0
+);
+
+INSERT INTO users_old (id, username, password, real_name, blurb, password_reset_required)
+SELECT id, username, password, real_name, blurb, password_reset_required FROM users;
+
+DROP INDEX IF EXISTS users_email_index;
+DROP INDEX IF EXISTS users_username_index;
+DROP TABLE users;
+ALTER TABLE users_old RENAME TO users;
+
+CREATE UNIQUE INDEX users_username_index ON users (
+   username
+);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE password_reset_tokens;
+DROP INDEX IF EXISTS password_reset_tokens_selector_index;
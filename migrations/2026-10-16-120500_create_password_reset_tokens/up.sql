@@ -0,0 +1,16 @@
+-- Your SQL goes here
+--
+-- Like auth tokens, reset tokens are presented as `{selector}.{secret}`,
+-- and only a digest of the secret is stored.
+CREATE TABLE password_reset_tokens (
+   id INTEGER PRIMARY KEY NOT NULL,
+   user_id INTEGER NOT NULL,
+   "timestamp" DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+   selector TEXT NOT NULL UNIQUE,
+   verifier BLOB NOT NULL,
+   FOREIGN KEY (user_id) REFERENCES users(id)
+);
+
+CREATE UNIQUE INDEX password_reset_tokens_selector_index ON password_reset_tokens (
+   selector
+);
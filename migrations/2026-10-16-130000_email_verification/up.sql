@@ -0,0 +1,19 @@
+-- Your SQL goes here
+ALTER TABLE users ADD COLUMN email_verified_at DATETIME;
+
+-- Like auth tokens, verification tokens are presented as `{selector}.{secret}`,
+-- and only a digest of the secret is stored. Each token records the address it
+-- was sent to, so that changing address invalidates it.
+CREATE TABLE email_verification_tokens (
+   id INTEGER PRIMARY KEY NOT NULL,
+   user_id INTEGER NOT NULL,
+   "timestamp" DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+   selector TEXT NOT NULL UNIQUE,
+   verifier BLOB NOT NULL,
+   email TEXT NOT NULL,
+   FOREIGN KEY (user_id) REFERENCES users(id)
+);
+
+CREATE UNIQUE INDEX email_verification_tokens_selector_index ON email_verification_tokens (
+   selector
+);
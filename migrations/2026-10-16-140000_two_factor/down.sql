@@ -0,0 +1,36 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE login_challenges;
+DROP INDEX IF EXISTS login_challenges_selector_index;
+DROP TABLE recovery_codes;
+DROP INDEX IF EXISTS recovery_codes_user_index;
+
+-- SQLite can't drop columns, so we rebuild the table without them.
+CREATE TABLE users_old (
+   id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+   username TEXT UNIQUE NOT NULL,
+   password TEXT NOT NULL,
+   real_name TEXT NOT NULL DEFAULT '',
+   blurb TEXT NOT NULL DEFAULT '',
+   password_reset_required BOOLEAN NOT NULL DEFAULT 0,
+   email TEXT,
+   email_verified_at DATETIME
+);
+
+INSERT INTO users_old (id, username, password, real_name, blurb, password_reset_required,
+                       email, email_verified_at)
+SELECT id, username, password, real_name, blurb, password_reset_required,
+       email, email_verified_at
+FROM users;
+
+DROP INDEX IF EXISTS users_email_index;
+DROP INDEX IF EXISTS users_username_index;
+DROP TABLE users;
+ALTER TABLE users_old RENAME TO users;
+
+CREATE UNIQUE INDEX users_username_index ON users (
+   username
+);
+
+CREATE UNIQUE INDEX users_email_index ON users (
+   email
+);
@@ -0,0 +1,40 @@
+-- Your SQL goes here
+--
+-- `totp_secret` is set as soon as a user starts enrolling, but two-factor
+-- authentication is only in force once `totp_enabled_at` is set too.
+-- `totp_last_step` is the time step of the last code accepted, so that
+-- codes can't be replayed.
+ALTER TABLE users ADD COLUMN totp_secret BLOB;
+ALTER TABLE users ADD COLUMN totp_enabled_at DATETIME;
+ALTER TABLE users ADD COLUMN totp_last_step INTEGER;
+
+-- Single-use codes for users who have lost their authenticator.
+-- Only a digest of each code is stored.
+CREATE TABLE recovery_codes (
+   id INTEGER PRIMARY KEY NOT NULL,
+   user_id INTEGER NOT NULL,
+   verifier BLOB NOT NULL,
+   FOREIGN KEY (user_id) REFERENCES users(id)
+);
+
+CREATE INDEX recovery_codes_user_index ON recovery_codes (
+   user_id
+);
+
+-- A login which has passed the password check, and is waiting on a code.
+-- Like auth tokens, challenges are presented as `{selector}.{secret}`.
+CREATE TABLE login_challenges (
+   id INTEGER PRIMARY KEY NOT NULL,
+   user_id INTEGER NOT NULL,
+   "timestamp" DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+   selector TEXT NOT NULL UNIQUE,
+   verifier BLOB NOT NULL,
+   device_name TEXT NOT NULL DEFAULT '',
+   user_agent TEXT,
+   attempts INTEGER NOT NULL DEFAULT 0,
+   FOREIGN KEY (user_id) REFERENCES users(id)
+);
+
+CREATE UNIQUE INDEX login_challenges_selector_index ON login_challenges (
+   selector
+);
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE failed_logins;
+DROP INDEX IF EXISTS failed_logins_username_index;
+DROP TABLE login_throttles;
+DROP INDEX IF EXISTS login_throttles_subject_index;
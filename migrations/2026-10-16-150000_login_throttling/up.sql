@@ -0,0 +1,31 @@
+-- Your SQL goes here
+--
+-- Failed logins are counted per subject, which is either a username
+-- (`user:{username}`) or a client address (`ip:{address}`). Once a subject
+-- has failed often enough, further attempts are refused until `blocked_until`.
+CREATE TABLE login_throttles (
+   id INTEGER PRIMARY KEY NOT NULL,
+   subject TEXT NOT NULL,
+   failures INTEGER NOT NULL DEFAULT 0,
+   last_failure DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+   blocked_until DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE UNIQUE INDEX login_throttles_subject_index ON login_throttles (
+   subject
+);
+
+-- Audit log of every failed login, whether the credentials were wrong
+-- or the attempt was refused because of throttling.
+CREATE TABLE failed_logins (
+   id INTEGER PRIMARY KEY NOT NULL,
+   username TEXT NOT NULL,
+   ip TEXT,
+   user_agent TEXT,
+   "timestamp" DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+   reason TEXT NOT NULL
+);
+
+CREATE INDEX failed_logins_username_index ON failed_logins (
+   username
+);
@@ -0,0 +1,28 @@
+-- Your SQL goes here
+--
+-- `username_normalized` is the username lowercased, and is what usernames are
+-- looked up and kept unique by. `username_skeleton` further folds characters
+-- which look alike, so that `Sonar`, `S0nar` and `sonar` can't all be taken
+-- by different people. The expressions here must match `username::normalize`
+-- and `username::skeleton`.
+--
+-- If existing usernames differ only in case, the unique index below can't be
+-- built; rename one of each pair by hand, then run this again.
+ALTER TABLE users ADD COLUMN username_normalized TEXT NOT NULL DEFAULT '';
+ALTER TABLE users ADD COLUMN username_skeleton TEXT NOT NULL DEFAULT '';
+
+UPDATE users SET username_normalized = lower(username);
+UPDATE users SET username_skeleton =
+   replace(replace(replace(replace(replace(replace(
+      username_normalized,
+      '0', 'o'), '1', 'l'), 'i', 'l'), 'rn', 'm'), 'vv', 'w'), 'cl', 'd');
+
+CREATE UNIQUE INDEX users_username_normalized_index ON users (
+   username_normalized
+);
+
+-- Not unique: existing lookalikes are left alone, and new ones are refused
+-- when the account is created.
+CREATE INDEX users_username_skeleton_index ON users (
+   username_skeleton
+);
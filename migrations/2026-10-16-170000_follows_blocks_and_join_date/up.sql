@@ -0,0 +1,44 @@
+-- Your SQL goes here
+--
+-- SQLite won't add a column defaulting to CURRENT_TIMESTAMP, so new users
+-- have `joined_at` set when they're inserted. We don't know when existing
+-- users joined, so theirs stays NULL.
+ALTER TABLE users ADD COLUMN joined_at DATETIME;
+
+-- `follower_id` follows `followed_id`
+CREATE TABLE follows (
+   id INTEGER PRIMARY KEY NOT NULL,
+   follower_id INTEGER NOT NULL,
+   followed_id INTEGER NOT NULL,
+   "timestamp" DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+   FOREIGN KEY (follower_id) REFERENCES users(id),
+   FOREIGN KEY (followed_id) REFERENCES users(id)
+);
+
+CREATE UNIQUE INDEX follows_follower_followed_index ON follows (
+   follower_id,
+   followed_id
+);
+
+CREATE INDEX follows_followed_index ON follows (
+   followed_id
+);
+
+-- `blocker_id` has blocked `blocked_id`. Neither may see the other.
+CREATE TABLE blocks (
+   id INTEGER PRIMARY KEY NOT NULL,
+   blocker_id INTEGER NOT NULL,
+   blocked_id INTEGER NOT NULL,
+   "timestamp" DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+   FOREIGN KEY (blocker_id) REFERENCES users(id),
+   FOREIGN KEY (blocked_id) REFERENCES users(id)
+);
+
+CREATE UNIQUE INDEX blocks_blocker_blocked_index ON blocks (
+   blocker_id,
+   blocked_id
+);
+
+CREATE INDEX blocks_blocked_index ON blocks (
+   blocked_id
+);
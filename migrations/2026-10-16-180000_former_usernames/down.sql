@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE former_usernames;
+DROP INDEX IF EXISTS former_usernames_normalized_index;
+DROP INDEX IF EXISTS former_usernames_skeleton_index;
+DROP INDEX IF EXISTS former_usernames_user_index;
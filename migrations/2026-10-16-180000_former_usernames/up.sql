@@ -0,0 +1,27 @@
+-- Your SQL goes here
+--
+-- Every username a user has given up. For a grace period after the change,
+-- requests for the old name redirect to the new one, and nobody else may
+-- claim it.
+CREATE TABLE former_usernames (
+   id INTEGER PRIMARY KEY NOT NULL,
+   user_id INTEGER NOT NULL,
+   username TEXT NOT NULL,
+   username_normalized TEXT NOT NULL,
+   username_skeleton TEXT NOT NULL,
+   changed_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+   FOREIGN KEY (user_id) REFERENCES users(id)
+);
+
+CREATE INDEX former_usernames_normalized_index ON former_usernames (
+   username_normalized,
+   changed_at DESC
+);
+
+CREATE INDEX former_usernames_skeleton_index ON former_usernames (
+   username_skeleton
+);
+
+CREATE INDEX former_usernames_user_index ON former_usernames (
+   user_id
+);
@@ -0,0 +1,50 @@
+-- This file should undo anything in `up.sql`
+--
+-- SQLite can't drop columns, so we rebuild the table without them.
+CREATE TABLE users_old (
+   id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+   username TEXT UNIQUE NOT NULL,
+   password TEXT NOT NULL,
+   real_name TEXT NOT NULL DEFAULT '',
+   blurb TEXT NOT NULL DEFAULT '',
+   password_reset_required BOOLEAN NOT NULL DEFAULT 0,
+   email TEXT,
+   email_verified_at DATETIME,
+   totp_secret BLOB,
+   totp_enabled_at DATETIME,
+   totp_last_step INTEGER,
+   username_normalized TEXT NOT NULL DEFAULT '',
+   username_skeleton TEXT NOT NULL DEFAULT '',
+   joined_at DATETIME
+);
+
+INSERT INTO users_old (id, username, password, real_name, blurb, password_reset_required,
+                       email, email_verified_at, totp_secret, totp_enabled_at, totp_last_step,
+                       username_normalized, username_skeleton, joined_at)
+SELECT id, username, password, real_name, blurb, password_reset_required,
+       email, email_verified_at, totp_secret, totp_enabled_at, totp_last_step,
+       username_normalized, username_skeleton, joined_at
+FROM users;
+
+DROP INDEX IF EXISTS users_username_skeleton_index;
+DROP INDEX IF EXISTS users_username_normalized_index;
+DROP INDEX IF EXISTS users_email_index;
+DROP INDEX IF EXISTS users_username_index;
+DROP TABLE users;
+ALTER TABLE users_old RENAME TO users;
+
+CREATE UNIQUE INDEX users_username_index ON users (
+   username
+);
+
+CREATE UNIQUE INDEX users_email_index ON users (
+   email
+);
+
+CREATE UNIQUE INDEX users_username_normalized_index ON users (
+   username_normalized
+);
+
+CREATE INDEX users_username_skeleton_index ON users (
+   username_skeleton
+);
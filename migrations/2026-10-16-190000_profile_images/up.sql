@@ -0,0 +1,6 @@
+-- Your SQL goes here
+--
+-- Each is a blob store key template, in which `{size}` stands for the label
+-- of each size the image was stored in.
+ALTER TABLE users ADD COLUMN avatar_key TEXT;
+ALTER TABLE users ADD COLUMN banner_key TEXT;
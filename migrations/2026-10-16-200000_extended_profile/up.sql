@@ -0,0 +1,19 @@
+-- Your SQL goes here
+ALTER TABLE users ADD COLUMN location TEXT NOT NULL DEFAULT '';
+ALTER TABLE users ADD COLUMN website TEXT NOT NULL DEFAULT '';
+ALTER TABLE users ADD COLUMN pronouns TEXT NOT NULL DEFAULT '';
+
+-- Custom label/value pairs shown on a user's profile, in `position` order
+CREATE TABLE profile_fields (
+   id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+   user_id INTEGER NOT NULL,
+   position INTEGER NOT NULL,
+   label TEXT NOT NULL,
+   value TEXT NOT NULL,
+   FOREIGN KEY (user_id) REFERENCES users(id)
+);
+
+CREATE UNIQUE INDEX profile_fields_user_position_index ON profile_fields (
+   user_id,
+   position
+);
@@ -0,0 +1,9 @@
+-- Your SQL goes here
+--
+-- Set when the user asks for their account to be deleted; the account is
+-- purged once the grace period after this has passed.
+ALTER TABLE users ADD COLUMN deactivated_at DATETIME;
+
+CREATE INDEX users_deactivated_at_index ON users (
+   deactivated_at
+);
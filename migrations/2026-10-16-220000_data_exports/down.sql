@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS data_exports_user_index;
+DROP TABLE data_exports;
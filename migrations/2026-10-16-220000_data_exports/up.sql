@@ -0,0 +1,18 @@
+-- Your SQL goes here
+--
+-- `status` is one of `pending`, `ready` or `failed`. Once an export is
+-- ready, `file_name` names its archive within `EXPORT_DIR`.
+CREATE TABLE data_exports (
+   id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+   user_id INTEGER NOT NULL,
+   status TEXT NOT NULL DEFAULT 'pending',
+   requested_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+   completed_at DATETIME,
+   file_name TEXT,
+   size BIGINT,
+   FOREIGN KEY (user_id) REFERENCES users(id)
+);
+
+CREATE INDEX data_exports_user_index ON data_exports (
+   user_id
+);
@@ -0,0 +1,27 @@
+-- This file should undo anything in `up.sql`
+--
+-- SQLite can't drop columns, so we rebuild the table without it. Deleted
+-- pings have no content left, so they go altogether.
+CREATE TABLE pings_old (
+   id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+   user_id INTEGER NOT NULL,
+   "timestamp" DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+   content TEXT NOT NULL,
+   likes INTEGER NOT NULL DEFAULT 0,
+   echoes INTEGER NOT NULL DEFAULT 0,
+   FOREIGN KEY (user_id) REFERENCES users(id)
+);
+
+INSERT INTO pings_old (id, user_id, "timestamp", content, likes, echoes)
+SELECT id, user_id, "timestamp", content, likes, echoes
+FROM pings
+WHERE deleted_at IS NULL;
+
+DROP INDEX IF EXISTS pings_user_timestamp_index;
+DROP TABLE pings;
+ALTER TABLE pings_old RENAME TO pings;
+
+CREATE INDEX pings_user_timestamp_index ON pings (
+   user_id,
+   "timestamp" DESC
+);
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+--
+-- Set when the author deletes a ping. The row is kept, without its content,
+-- so that its permalink can say it's gone rather than that it never was.
+ALTER TABLE pings ADD COLUMN deleted_at DATETIME;
@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX follows_follower_timestamp_index;
+DROP INDEX follows_followed_timestamp_index;
+
+CREATE INDEX follows_followed_index ON follows (
+   followed_id
+);
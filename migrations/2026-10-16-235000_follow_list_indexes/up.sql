@@ -0,0 +1,20 @@
+-- Your SQL goes here
+--
+-- The `follows` table itself came with blocks and join dates. Listing a
+-- user's followers, or whom they follow, goes newest first, so index both
+-- directions by time. The new followed index supersedes the old one.
+--
+-- This was first released as `2026-10-16-240000_follow_list_indexes`, which
+-- isn't a valid time. Databases which ran it already have these indexes, so
+-- nothing here may fail if they exist.
+DROP INDEX IF EXISTS follows_followed_index;
+
+CREATE INDEX IF NOT EXISTS follows_followed_timestamp_index ON follows (
+   followed_id,
+   "timestamp" DESC
+);
+
+CREATE INDEX IF NOT EXISTS follows_follower_timestamp_index ON follows (
+   follower_id,
+   "timestamp" DESC
+);
@@ -3,5 +3,23 @@
 /// Secure password handling
 pub mod pw;
 
+/// Rules for what makes an acceptable password
+pub mod policy;
+
 /// Request guard which checks that a valid authorization token was provided
 pub mod token;
+
+/// Single-use tokens with which users reset forgotten passwords
+pub mod reset;
+
+/// Single-use tokens with which users prove they own an email address
+pub mod verification;
+
+/// RFC 6238 time-based one-time passwords
+pub mod totp;
+
+/// Second-factor enrollment, and the challenges which users with it enabled answer to sign in
+pub mod two_factor;
+
+/// Tracking of failed logins, and the backoff and lockout that follow them
+pub mod throttle;
@@ -0,0 +1,217 @@
+use std::collections::{HashMap, HashSet};
+
+use config::{PASSWORD_MIN_ENTROPY_BITS, PASSWORD_MIN_LENGTH};
+
+/// Usernames shorter than this aren't looked for inside passwords;
+/// otherwise a user called `a` could hardly choose any password at all.
+const MIN_USERNAME_MATCH_LENGTH: usize = 3;
+
+lazy_static! {
+    static ref COMMON_PASSWORDS: HashSet<&'static str> =
+        include_str!("../../data/common_passwords.txt")
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .collect();
+}
+
+/// A reason a password was rejected
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Violation {
+    /// Fewer than `minimum` characters
+    TooShort { minimum: usize },
+    /// Too repetitive or sequential to be hard to guess
+    TooPredictable,
+    /// Contains the user's username
+    ContainsUsername,
+    /// Appears in the bundled list of common passwords
+    Common,
+}
+
+impl Violation {
+    /// A stable, machine-readable code which clients can use to explain the violation
+    pub fn code(&self) -> &'static str {
+        match *self {
+            Violation::TooShort { .. } => "too_short",
+            Violation::TooPredictable => "too_predictable",
+            Violation::ContainsUsername => "contains_username",
+            Violation::Common => "common",
+        }
+    }
+
+    /// A human-readable explanation of the violation
+    pub fn message(&self) -> String {
+        match *self {
+            Violation::TooShort { minimum } => {
+                format!("Password must be at least {} characters long", minimum)
+            }
+            Violation::TooPredictable => String::from(
+                "Password is too repetitive or predictable",
+            ),
+            Violation::ContainsUsername => String::from("Password must not contain your username"),
+            Violation::Common => String::from("Password is too common; pick another"),
+        }
+    }
+}
+
+/// What makes a password acceptable
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Policy {
+    /// Minimum length, in Unicode scalar values
+    pub min_length: usize,
+    /// Minimum estimated entropy, in bits
+    pub min_entropy: f64,
+}
+
+impl Policy {
+    /// The currently configured policy
+    pub fn current() -> Policy {
+        Policy {
+            min_length: *PASSWORD_MIN_LENGTH,
+            min_entropy: *PASSWORD_MIN_ENTROPY_BITS,
+        }
+    }
+
+    /// Check a proposed password for the given user.
+    ///
+    /// Returns every rule the password breaks, so that they can all be
+    /// explained at once.
+    pub fn check(&self, password: &str, username: &str) -> Result<(), Vec<Violation>> {
+        let mut violations = Vec::new();
+
+        if password.chars().count() < self.min_length {
+            violations.push(Violation::TooShort { minimum: self.min_length });
+        }
+        if estimate_entropy(password) < self.min_entropy {
+            violations.push(Violation::TooPredictable);
+        }
+
+        let lowercase = password.to_lowercase();
+        if username.chars().count() >= MIN_USERNAME_MATCH_LENGTH &&
+            lowercase.contains(&username.to_lowercase())
+        {
+            violations.push(Violation::ContainsUsername);
+        }
+        if COMMON_PASSWORDS.contains(lowercase.as_str()) {
+            violations.push(Violation::Common);
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+}
+
+/// Estimate how many bits of entropy a password has.
+///
+/// This is the Shannon entropy of the password's characters, multiplied by
+/// the number of characters which don't just repeat or step on from the one
+/// before. It's a rough guide, not a guarantee: it catches `aaaaaaaaaaaaaaaa`
+/// and `abcdefghijklmnop`, but not dictionary words.
+pub fn estimate_entropy(password: &str) -> f64 {
+    let chars: Vec<char> = password.chars().collect();
+    if chars.is_empty() {
+        return 0.0;
+    }
+
+    let mut counts = HashMap::new();
+    for c in chars.iter() {
+        *counts.entry(c).or_insert(0) += 1;
+    }
+    let total = chars.len() as f64;
+    let bits_per_char: f64 = counts
+        .values()
+        .map(|&count| {
+            let p = count as f64 / total;
+            -p * p.log2()
+        })
+        .sum();
+
+    let unpredictable = 1 +
+        chars
+            .windows(2)
+            .filter(|pair| (pair[1] as i64 - pair[0] as i64).abs() > 1)
+            .count();
+    bits_per_char * unpredictable as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> Policy {
+        Policy {
+            min_length: 16,
+            min_entropy: 40.0,
+        }
+    }
+
+    #[test]
+    fn test_good_password() {
+        assert_eq!(policy().check("correct horse battery staple", "alice"), Ok(()));
+    }
+
+    #[test]
+    fn test_length_counts_characters_not_bytes() {
+        // 9 characters, but 27 bytes
+        let password = "日本語のパスワード";
+        assert!(password.len() >= 16);
+        assert!(
+            policy()
+                .check(password, "alice")
+                .unwrap_err()
+                .contains(&Violation::TooShort { minimum: 16 })
+        );
+    }
+
+    #[test]
+    fn test_repetition_is_predictable() {
+        assert_eq!(estimate_entropy("aaaaaaaaaaaaaaaa"), 0.0);
+        assert!(
+            policy()
+                .check("aaaaaaaaaaaaaaaa", "alice")
+                .unwrap_err()
+                .contains(&Violation::TooPredictable)
+        );
+    }
+
+    #[test]
+    fn test_sequences_are_predictable() {
+        assert!(estimate_entropy("abcdefghijklmnop") < 40.0);
+        assert!(estimate_entropy("9876543210987654") < 40.0);
+    }
+
+    #[test]
+    fn test_contains_username() {
+        let violations = policy()
+            .check("my name is Alice and I like tea", "alice")
+            .unwrap_err();
+        assert_eq!(violations, vec![Violation::ContainsUsername]);
+    }
+
+    #[test]
+    fn test_short_usernames_are_not_matched() {
+        assert_eq!(policy().check("correct horse battery staple", "or"), Ok(()));
+    }
+
+    #[test]
+    fn test_common() {
+        let violations = policy().check("CorrectHorseBatteryStaple", "alice").unwrap_err();
+        assert_eq!(violations, vec![Violation::Common]);
+    }
+
+    #[test]
+    fn test_codes_are_distinct() {
+        let codes: HashSet<_> = [
+            Violation::TooShort { minimum: 16 },
+            Violation::TooPredictable,
+            Violation::ContainsUsername,
+            Violation::Common,
+        ].iter()
+            .map(|v| v.code())
+            .collect();
+        assert_eq!(codes.len(), 4);
+    }
+}
@@ -1,57 +1,224 @@
-
+use argon2;
 use argon2rs;
+use base64;
+use config::{ARGON2_ITERATIONS, ARGON2_MEMORY_KIB, ARGON2_PARALLELISM, PASSWORD_PEPPERS,
+             PASSWORD_PEPPER_ID};
 use rand::{Rng, OsRng};
+use ring::{constant_time, digest, hmac};
 use std::fmt;
 
-/// How long should the salt be.
+/// How long should the salt be, in bytes.
 ///
-/// Good practice is apparently to use the same number of random bytes as the
-/// hasher outputs. However, for simplicity's sake, we're limited to the characters
-/// `[a-zA-Z0-9]`, which reduces the entropy per byte from 256 to 62; roughly a quarter.
-/// Therefore, we quadruple the salt's length in order to retain entropy.
-const SALT_LENGTH: usize = argon2rs::defaults::LENGTH * 4;
+/// Current salts are stored base64-encoded, so they can use the full range of
+/// each byte; the argon2 authors recommend 16 bytes.
+const SALT_LENGTH: usize = 16;
 /// How long is the password hash.
 ///
 /// We just take this from the hashing library.
 const HASH_LENGTH: usize = argon2rs::defaults::LENGTH;
+/// The argon2 version we hash with; `v=19` in the PHC string format.
+const ARGON2_VERSION: u32 = 0x13;
+
+/// Cost parameters for argon2id
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Params {
+    /// Memory cost, in KiB
+    pub memory: u32,
+    /// Time cost: the number of passes over memory
+    pub iterations: u32,
+    /// Degree of parallelism: the number of lanes
+    pub parallelism: u32,
+}
+
+impl Params {
+    /// The parameters currently configured for newly hashed passwords
+    pub fn current() -> Params {
+        Params {
+            memory: *ARGON2_MEMORY_KIB,
+            iterations: *ARGON2_ITERATIONS,
+            parallelism: *ARGON2_PARALLELISM,
+        }
+    }
+
+    /// Whether these parameters are at least as costly as `other` in every respect
+    fn at_least(&self, other: &Params) -> bool {
+        self.memory >= other.memory && self.iterations >= other.iterations &&
+            self.parallelism >= other.parallelism
+    }
+
+    /// Check that argon2 accepts these parameters
+    ///
+    /// New passwords are hashed with the configured parameters, so they're
+    /// checked once at startup rather than failing every signup.
+    pub fn check(&self) -> Result<(), argon2::Error> {
+        self.hash(b"password", &[0; SALT_LENGTH]).map(|_| ())
+    }
+
+    /// Hash the input with these parameters
+    ///
+    /// Fails if argon2 rejects the parameters, such as a stored hash with no lanes.
+    fn hash(&self, input: &[u8], salt: &[u8]) -> Result<Vec<u8>, argon2::Error> {
+        let config = argon2::Config {
+            variant: argon2::Variant::Argon2id,
+            version: argon2::Version::Version13,
+            mem_cost: self.memory,
+            time_cost: self.iterations,
+            lanes: self.parallelism,
+            hash_length: HASH_LENGTH as u32,
+            ..argon2::Config::default()
+        };
+        argon2::hash_raw(input, salt, &config)
+    }
+}
+
+/// The hashing method used for a particular password, and everything it needs
+/// besides the password itself.
+enum Method {
+    /// `$argon2${salt}${hash}$`: argon2i with argon2rs's default parameters,
+    /// an ascii salt, and a hex hash.
+    ///
+    /// We no longer create these, but we can still validate them.
+    Legacy { salt: String },
+    /// `$argon2id$v=19$m={memory},t={iterations},p={parallelism}[,keyid={keyid}]${salt}${hash}`:
+    /// the PHC string format, with base64 salt and hash.
+    ///
+    /// If `keyid` is present, the password was peppered with that key before hashing.
+    Argon2id {
+        params: Params,
+        salt: Vec<u8>,
+        keyid: Option<String>,
+    },
+}
+
+/// Prepare a password for hashing by applying the pepper with the given key id, if any.
+///
+/// The pepper is a secret key kept out of the database: we hash the HMAC of the
+/// password under that key, rather than the password itself, so that a stolen
+/// `users` table is no use without the key as well.
+///
+/// Returns `None` if the key id isn't configured.
+fn pepper(password: &str, keyid: &Option<String>) -> Option<Vec<u8>> {
+    match *keyid {
+        None => Some(password.as_bytes().to_vec()),
+        Some(ref keyid) => {
+            let key = hmac::SigningKey::new(&digest::SHA256, PASSWORD_PEPPERS.get(keyid)?);
+            Some(hmac::sign(&key, password.as_bytes()).as_ref().to_vec())
+        }
+    }
+}
 
 /// Salted Password representation.
 ///
 /// Use this to manage automatically salting and validating user passwords.
 ///
-/// Generally, passwords are stored in the DB as a string of the form
-/// `${method}${salt}${hash}$`. Salts are generated independently for each
-/// password.
+/// Passwords are stored in the DB as a string which records the method
+/// used to hash them, that method's cost parameters, the salt, and the hash.
+/// New passwords use argon2id in the PHC string format:
+/// `$argon2id$v=19$m={memory},t={iterations},p={parallelism}[,keyid={keyid}]${salt}${hash}`.
+/// Older passwords may use the legacy format `$argon2${salt}${hash}$`.
+/// Salts are generated independently for each password. If a pepper is configured,
+/// new passwords are peppered with it, and its key id is recorded alongside.
 ///
-/// Possibly in the future, multiple methods will be allowed; for now, the set
-/// of allowed hash methods is `{argon2}`.
+/// Passwords hashed with an outdated method, with weaker parameters than
+/// those currently configured, or with a pepper other than the current one,
+/// report that they need an upgrade; callers should rehash them when they
+/// next have the plaintext available.
 ///
 /// This struct doesn't manage actually storing or retrieving anything from
 /// a database or other storage method; it simply provides methods for creating,
 /// parsing, and validating passwords which have been stringified in the proper format.
 pub struct SaltyPassword {
-    salt: String,
-    hash: [u8; HASH_LENGTH],
+    method: Method,
+    hash: Vec<u8>,
 }
 
 impl SaltyPassword {
-    /// Generate a salt and hash the supplied password with it.
+    /// Generate a salt and hash the supplied password with it, using the
+    /// currently configured parameters.
     pub fn new(password: &str) -> SaltyPassword {
-        let salt: String = OsRng::new()
+        SaltyPassword::with_params(password, Params::current())
+    }
+
+    /// Generate a salt and hash the supplied password with it, using the given
+    /// parameters and the current pepper.
+    ///
+    /// Panics if argon2 rejects the parameters; `main` checks the configured
+    /// ones with `Params::check` before serving anything.
+    pub fn with_params(password: &str, params: Params) -> SaltyPassword {
+        let mut salt = vec![0; SALT_LENGTH];
+        OsRng::new()
             .expect("Failed to access OS RNG; aborting")
-            .gen_ascii_chars()
-            .take(SALT_LENGTH)
-            .collect();
+            .fill_bytes(&mut salt);
+        let keyid = PASSWORD_PEPPER_ID.clone();
+        let input = pepper(password, &keyid).expect("PASSWORD_PEPPER_ID is always configured");
         SaltyPassword {
-            hash: argon2rs::argon2i_simple(password, &salt),
-            salt: salt,
+            hash: params.hash(&input, &salt).expect(
+                "argon2 rejected the password parameters",
+            ),
+            method: Method::Argon2id {
+                params: params,
+                salt: salt,
+                keyid: keyid,
+            },
+        }
+    }
+
+    pub fn parse(field: &str) -> Option<SaltyPassword> {
+        if field.starts_with("$argon2$") {
+            SaltyPassword::parse_legacy(field)
+        } else {
+            SaltyPassword::parse_phc(field)
         }
     }
 
-    pub fn parse(mut field: &str) -> Option<SaltyPassword> {
+    fn parse_phc(field: &str) -> Option<SaltyPassword> {
+        let mut parts = field.split('$');
+        // the field begins with '$', so the first part is always empty
+        if parts.next()? != "" || parts.next()? != "argon2id" {
+            return None;
+        }
+        if parts.next()? != format!("v={}", ARGON2_VERSION) {
+            return None;
+        }
+
+        let (mut memory, mut iterations, mut parallelism, mut keyid) = (None, None, None, None);
+        for param in parts.next()?.split(',') {
+            let split_index = param.find('=')?;
+            let (name, value) = param.split_at(split_index);
+            let value = &value[1..];
+            match name {
+                "m" => memory = Some(value.parse::<u32>().ok()?),
+                "t" => iterations = Some(value.parse::<u32>().ok()?),
+                "p" => parallelism = Some(value.parse::<u32>().ok()?),
+                "keyid" if !value.is_empty() => keyid = Some(value.to_string()),
+                _ => return None,
+            }
+        }
+
+        let salt = base64::decode_config(parts.next()?, base64::STANDARD_NO_PAD).ok()?;
+        let hash = base64::decode_config(parts.next()?, base64::STANDARD_NO_PAD).ok()?;
+        if parts.next().is_some() || hash.len() != HASH_LENGTH {
+            return None;
+        }
+
+        Some(SaltyPassword {
+            method: Method::Argon2id {
+                params: Params {
+                    memory: memory?,
+                    iterations: iterations?,
+                    parallelism: parallelism?,
+                },
+                salt: salt,
+                keyid: keyid,
+            },
+            hash: hash,
+        })
+    }
+
+    fn parse_legacy(mut field: &str) -> Option<SaltyPassword> {
         // trim off constant bits of the field
         let prefix = "$argon2$";
-        if !(field.starts_with(prefix) && field.ends_with("$")) {
+        if !(field.len() > prefix.len() && field.starts_with(prefix) && field.ends_with("$")) {
             return None;
         }
         field = &field[prefix.len()..(field.len() - 1)];
@@ -66,7 +233,7 @@ impl SaltyPassword {
         if hash_chars.len() != HASH_LENGTH * 2 {
             return None;
         }
-        let mut hash = [0; HASH_LENGTH];
+        let mut hash = vec![0; HASH_LENGTH];
         for index in 0..HASH_LENGTH {
             let begin = index * 2;
             let end = begin + 2;
@@ -77,8 +244,8 @@ impl SaltyPassword {
         }
 
         Some(SaltyPassword {
+            method: Method::Legacy { salt: salt.to_string() },
             hash: hash,
-            salt: salt.to_string(),
         })
     }
 
@@ -87,21 +254,200 @@ impl SaltyPassword {
     /// Generally speaking, you'll want to create a SaltyPassword from the
     /// password field in the database, and then use that to validate your
     /// maybe password.
+    ///
+    /// The hashes are compared in constant time. A password peppered with a key
+    /// which is no longer configured, or stored with parameters which argon2
+    /// rejects, never validates.
     pub fn validate(&self, password: &str) -> bool {
-        self.hash == argon2rs::argon2i_simple(password, &self.salt)
+        let hash = match self.method {
+            Method::Legacy { ref salt } => argon2rs::argon2i_simple(password, salt).to_vec(),
+            Method::Argon2id {
+                ref params,
+                ref salt,
+                ref keyid,
+            } => {
+                match pepper(password, keyid).map(|input| params.hash(&input, salt)) {
+                    Some(Ok(hash)) => hash,
+                    _ => return false,
+                }
+            }
+        };
+        constant_time::verify_slices_are_equal(&self.hash, &hash).is_ok()
+    }
+
+    /// Check whether this password should be rehashed.
+    ///
+    /// That's the case if it uses an outdated method, if any of its cost
+    /// parameters are weaker than those currently configured, or if it isn't
+    /// peppered with the current pepper.
+    pub fn needs_upgrade(&self) -> bool {
+        match self.method {
+            Method::Legacy { .. } => true,
+            Method::Argon2id {
+                ref params,
+                ref keyid,
+                ..
+            } => !params.at_least(&Params::current()) || *keyid != *PASSWORD_PEPPER_ID,
+        }
     }
 }
 
 impl fmt::Display for SaltyPassword {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f, "${method}${salt}$",
-            method = "argon2",
-            salt = self.salt,
-        )?;
-        for byte in self.hash.iter() {
-            write!(f, "{:x}", byte)?;
-        }
-        write!(f, "$")
+        match self.method {
+            Method::Legacy { ref salt } => {
+                write!(
+                    f, "${method}${salt}$",
+                    method = "argon2",
+                    salt = salt,
+                )?;
+                // Each byte must take exactly two digits; `parse` depends on it.
+                for byte in self.hash.iter() {
+                    write!(f, "{:02x}", byte)?;
+                }
+                write!(f, "$")
+            }
+            Method::Argon2id {
+                ref params,
+                ref salt,
+                ref keyid,
+            } => {
+                write!(
+                    f, "${method}$v={version}$m={m},t={t},p={p}",
+                    method = "argon2id",
+                    version = ARGON2_VERSION,
+                    m = params.memory,
+                    t = params.iterations,
+                    p = params.parallelism,
+                )?;
+                if let Some(ref keyid) = *keyid {
+                    write!(f, ",keyid={}", keyid)?;
+                }
+                write!(
+                    f, "${salt}${hash}",
+                    salt = base64::encode_config(salt, base64::STANDARD_NO_PAD),
+                    hash = base64::encode_config(&self.hash, base64::STANDARD_NO_PAD),
+                )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// Cheap parameters, so the tests don't take all day
+    const WEAK: Params = Params {
+        memory: 8,
+        iterations: 1,
+        parallelism: 1,
+    };
+
+    fn legacy(password: &str) -> SaltyPassword {
+        let salt: String = OsRng::new()
+            .unwrap()
+            .gen_ascii_chars()
+            .take(argon2rs::defaults::LENGTH * 4)
+            .collect();
+        SaltyPassword {
+            hash: argon2rs::argon2i_simple(password, &salt).to_vec(),
+            method: Method::Legacy { salt: salt },
+        }
+    }
+
+    #[test]
+    fn test_argon2id_round_trip() {
+        let stored = SaltyPassword::with_params("correct horse", WEAK).to_string();
+        assert!(stored.starts_with("$argon2id$v=19$m=8,t=1,p=1"));
+
+        let parsed = SaltyPassword::parse(&stored).expect("failed to parse own output");
+        assert_eq!(parsed.to_string(), stored);
+        assert!(parsed.validate("correct horse"));
+        assert!(!parsed.validate("battery staple"));
+    }
+
+    #[test]
+    fn test_rejected_params_never_validate() {
+        let stored = SaltyPassword::with_params("correct horse", WEAK)
+            .to_string()
+            .replace("p=1", "p=0");
+        let parsed = SaltyPassword::parse(&stored).expect("failed to parse");
+        assert!(!parsed.validate("correct horse"));
+    }
+
+    #[test]
+    fn test_needs_upgrade() {
+        assert!(legacy("correct horse").needs_upgrade());
+        assert!(SaltyPassword::with_params("correct horse", WEAK).needs_upgrade());
+        assert!(!SaltyPassword::new("correct horse").needs_upgrade());
+    }
+
+    #[test]
+    fn test_legacy_still_validates() {
+        let password = legacy("correct horse");
+        assert!(password.validate("correct horse"));
+        assert!(!password.validate("battery staple"));
+    }
+
+    #[test]
+    fn test_legacy_keeps_leading_zeros() {
+        let password = SaltyPassword {
+            hash: vec![0x0a; HASH_LENGTH],
+            method: Method::Legacy { salt: String::from("salt") },
+        };
+        let stored = password.to_string();
+        assert_eq!(stored.len(), "$argon2$salt$$".len() + HASH_LENGTH * 2);
+        assert_eq!(SaltyPassword::parse(&stored).unwrap().hash, password.hash);
+    }
+
+    proptest! {
+        #[test]
+        fn test_legacy_round_trip(
+            salt in "[a-zA-Z0-9]{128}",
+            hash in prop::collection::vec(any::<u8>(), HASH_LENGTH),
+        ) {
+            let password = SaltyPassword {
+                hash: hash,
+                method: Method::Legacy { salt: salt },
+            };
+            let stored = password.to_string();
+            let parsed = SaltyPassword::parse(&stored).expect("failed to parse own output");
+            prop_assert_eq!(&parsed.hash, &password.hash);
+            prop_assert_eq!(parsed.to_string(), stored);
+        }
+
+        #[test]
+        fn test_argon2id_format_round_trip(
+            memory in 8u32..1 << 22,
+            iterations in 1u32..16,
+            parallelism in 1u32..16,
+            salt in prop::collection::vec(any::<u8>(), 8..64),
+            keyid in prop::option::of("[a-zA-Z0-9]{1,8}"),
+            hash in prop::collection::vec(any::<u8>(), HASH_LENGTH),
+        ) {
+            let password = SaltyPassword {
+                hash: hash,
+                method: Method::Argon2id {
+                    params: Params {
+                        memory: memory,
+                        iterations: iterations,
+                        parallelism: parallelism,
+                    },
+                    salt: salt,
+                    keyid: keyid,
+                },
+            };
+            let stored = password.to_string();
+            let parsed = SaltyPassword::parse(&stored).expect("failed to parse own output");
+            prop_assert_eq!(&parsed.hash, &password.hash);
+            prop_assert_eq!(parsed.to_string(), stored);
+        }
+
+        #[test]
+        fn test_parse_never_panics(field in "\\PC*") {
+            let _ = SaltyPassword::parse(&field);
+        }
     }
 }
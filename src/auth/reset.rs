@@ -0,0 +1,129 @@
+use chrono::Utc;
+use diesel::{delete, insert};
+use diesel::prelude::*;
+
+use auth::token::{digest_secret, random_string, split_key, verify_secret, SECRET_LENGTH,
+                  SELECTOR_LENGTH};
+use config::PASSWORD_RESET_LIFETIME;
+use db::{Connection, CONNECTION_POOL};
+use models::{User, PasswordResetToken, NewPasswordResetToken};
+
+/// Password Reset
+///
+/// A user who has forgotten their password can ask for a reset token to be
+/// emailed to them, and exchange it for a new password. Tokens can only be
+/// used once, and expire after `PASSWORD_RESET_LIFETIME`. Only a digest of
+/// each token's secret is stored.
+pub struct PasswordReset;
+
+impl PasswordReset {
+    /// Create and return a reset token for the specified user.
+    ///
+    /// Any reset tokens previously issued to this user stop working.
+    ///
+    /// Returns the created key, of the form `{selector}.{secret}`
+    pub fn create_for(user: &User) -> Result<String, &'static str> {
+        use schema::password_reset_tokens::dsl::*;
+
+        let connection = CONNECTION_POOL.get().map_err(
+            |_| "Couldn't get connection from pool",
+        )?;
+
+        let new_selector = random_string(SELECTOR_LENGTH)?;
+        let secret = random_string(SECRET_LENGTH)?;
+
+        delete(password_reset_tokens.filter(user_id.eq(user.id)))
+            .execute(&*connection)
+            .map_err(|_| "Couldn't delete existing reset tokens")?;
+
+        insert(&NewPasswordResetToken {
+            user_id: user.id,
+            selector: &new_selector,
+            verifier: &digest_secret(&secret),
+        }).into(password_reset_tokens)
+            .execute(&*connection)
+            .map_err(|_| "Failed to insert reset token")?;
+
+        Ok(format!("{}.{}", new_selector, secret))
+    }
+
+    /// Find the user a reset token was issued to, without using it up.
+    ///
+    /// Returns `None` if the key presented isn't valid or has expired.
+    pub fn user_for(incoming_key: &str) -> Result<Option<User>, &'static str> {
+        let connection = CONNECTION_POOL.get().map_err(
+            |_| "Couldn't get connection from pool",
+        )?;
+
+        let token = match find_token(&*connection, incoming_key)? {
+            Some(token) => token,
+            None => return Ok(None),
+        };
+        if is_expired(&token) {
+            return Ok(None);
+        }
+        load_user(&*connection, &token).map(Some)
+    }
+
+    /// Use up a reset token.
+    ///
+    /// Returns the user it was issued to, or `None` if the key presented
+    /// wasn't valid or has expired. Either way, the key won't work again.
+    pub fn redeem(incoming_key: &str) -> Result<Option<User>, &'static str> {
+        use schema::password_reset_tokens::dsl::*;
+
+        let connection = CONNECTION_POOL.get().map_err(
+            |_| "Couldn't get connection from pool",
+        )?;
+
+        let token = match find_token(&*connection, incoming_key)? {
+            Some(token) => token,
+            None => return Ok(None),
+        };
+
+        delete(password_reset_tokens.find(token.id))
+            .execute(&*connection)
+            .map_err(|_| "Couldn't delete used reset token")?;
+        if is_expired(&token) {
+            return Ok(None);
+        }
+
+        load_user(&*connection, &token).map(Some)
+    }
+}
+
+/// Look up the token a key refers to, if the key is valid
+fn find_token(
+    connection: &Connection,
+    incoming_key: &str,
+) -> Result<Option<PasswordResetToken>, &'static str> {
+    use schema::password_reset_tokens::dsl::*;
+
+    let (incoming_selector, incoming_secret) = match split_key(incoming_key) {
+        Some(parts) => parts,
+        None => return Ok(None),
+    };
+    let token = match password_reset_tokens
+        .filter(selector.eq(incoming_selector))
+        .first::<PasswordResetToken>(connection)
+        .optional()
+        .map_err(|_| "Failed to look up reset token")? {
+        Some(token) => token,
+        None => return Ok(None),
+    };
+    if !verify_secret(&token.verifier, incoming_secret) {
+        return Ok(None);
+    }
+    Ok(Some(token))
+}
+
+fn is_expired(token: &PasswordResetToken) -> bool {
+    Utc::now().naive_utc().signed_duration_since(token.timestamp) > *PASSWORD_RESET_LIFETIME
+}
+
+fn load_user(connection: &Connection, token: &PasswordResetToken) -> Result<User, &'static str> {
+    use schema::users::dsl::*;
+    users.find(token.user_id).first::<User>(connection).map_err(
+        |_| "Failed to look up user",
+    )
+}
@@ -0,0 +1,312 @@
+use std::cmp::{max, min};
+
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::{delete, insert, update};
+use diesel::prelude::*;
+use diesel::result::QueryResult;
+use diesel::types::Text;
+
+use config::{LOGIN_USERNAME_FREE_ATTEMPTS, LOGIN_USERNAME_LOCKOUT_THRESHOLD,
+             LOGIN_IP_FREE_ATTEMPTS, LOGIN_IP_LOCKOUT_THRESHOLD, LOGIN_LOCKOUT_DURATION,
+             LOGIN_FAILURE_MEMORY};
+use db::Connection;
+use models::{Throttle, NewThrottle, NewFailedLogin};
+use username::normalize;
+
+/// Audit reason recorded when the credentials presented were wrong
+pub const REASON_BAD_CREDENTIALS: &'static str = "bad_credentials";
+/// Audit reason recorded when an attempt was refused without checking it
+pub const REASON_THROTTLED: &'static str = "throttled";
+
+/// How failed logins against a single subject are punished
+struct Policy {
+    /// How many failures are allowed before any backoff
+    free_attempts: i32,
+    /// How many failures lock the subject out entirely
+    lockout_threshold: i32,
+    /// How long a lockout lasts
+    lockout: Duration,
+}
+
+impl Policy {
+    fn for_username() -> Policy {
+        Policy {
+            free_attempts: *LOGIN_USERNAME_FREE_ATTEMPTS,
+            lockout_threshold: *LOGIN_USERNAME_LOCKOUT_THRESHOLD,
+            lockout: *LOGIN_LOCKOUT_DURATION,
+        }
+    }
+
+    fn for_ip() -> Policy {
+        Policy {
+            free_attempts: *LOGIN_IP_FREE_ATTEMPTS,
+            lockout_threshold: *LOGIN_IP_LOCKOUT_THRESHOLD,
+            lockout: *LOGIN_LOCKOUT_DURATION,
+        }
+    }
+
+    /// How long a subject must wait after its `failures`th consecutive failure
+    ///
+    /// The wait doubles with each failure past the free ones, starting at one
+    /// second, and never exceeds the lockout.
+    fn backoff(&self, failures: i32) -> Duration {
+        if failures >= self.lockout_threshold {
+            self.lockout
+        } else if failures <= self.free_attempts {
+            Duration::zero()
+        } else {
+            let exponent = min(failures - self.free_attempts - 1, 30) as u32;
+            min(Duration::seconds(2_i64.pow(exponent)), self.lockout)
+        }
+    }
+}
+
+// Failed logins record the username as it was typed, so compare them ignoring
+// case; like `username::normalize`, SQLite's `lower` only folds ASCII.
+sql_function!(lower, lower_t, (x: Text) -> Text);
+
+fn username_subject(username: &str) -> String {
+    format!("user:{}", normalize(username))
+}
+
+fn ip_subject(ip: &str) -> String {
+    format!("ip:{}", ip)
+}
+
+/// Login Throttle
+///
+/// Counts failed logins per username and per client address. Past a few
+/// failures, each subject must wait exponentially longer before trying
+/// again, and past many, it's locked out for a while. Every failed login
+/// is also written to the `failed_logins` audit table.
+///
+/// A login fails whenever anything the client presents is wrong: the
+/// password, or the second-factor code which follows it.
+pub struct LoginThrottle;
+
+impl LoginThrottle {
+    /// How long a client must wait before it may try to log in as `username`
+    /// from `ip`, or `None` if it may try now.
+    pub fn retry_after(
+        connection: &Connection,
+        username: &str,
+        ip: Option<&str>,
+    ) -> Result<Option<Duration>, &'static str> {
+        let now = Utc::now().naive_utc();
+
+        let mut wait = blocked_for(connection, &username_subject(username), now)?;
+        if let Some(ip) = ip {
+            wait = max(wait, blocked_for(connection, &ip_subject(ip), now)?);
+        }
+        Ok(if wait > Duration::zero() {
+            Some(wait)
+        } else {
+            None
+        })
+    }
+
+    /// Record a login which presented the wrong credentials
+    ///
+    /// This counts against both the username and the address.
+    pub fn record_failure(
+        connection: &Connection,
+        username: &str,
+        ip: Option<&str>,
+        user_agent: Option<&str>,
+    ) -> Result<(), &'static str> {
+        let now = Utc::now().naive_utc();
+
+        audit(connection, username, ip, user_agent, REASON_BAD_CREDENTIALS)?;
+        bump(
+            connection,
+            &username_subject(username),
+            &Policy::for_username(),
+            now,
+        )?;
+        if let Some(ip) = ip {
+            bump(connection, &ip_subject(ip), &Policy::for_ip(), now)?;
+        }
+        Ok(())
+    }
+
+    /// Record a login which was refused because of throttling
+    ///
+    /// This is audited, but doesn't extend the wait.
+    pub fn record_refusal(
+        connection: &Connection,
+        username: &str,
+        ip: Option<&str>,
+        user_agent: Option<&str>,
+    ) -> Result<(), &'static str> {
+        audit(connection, username, ip, user_agent, REASON_THROTTLED)
+    }
+
+    /// Record a successful login, forgiving the username's failures
+    ///
+    /// A login only succeeds once every factor has been checked, so call this
+    /// just before issuing a token, not as soon as the password is right.
+    ///
+    /// The address's failures stand: otherwise an attacker could clear them
+    /// by periodically logging in to an account of their own.
+    pub fn record_success(connection: &Connection, username: &str) -> Result<(), &'static str> {
+        use schema::login_throttles::dsl::*;
+
+        delete(login_throttles.filter(subject.eq(username_subject(username))))
+            .execute(connection)
+            .map_err(|_| "Couldn't clear login throttle")?;
+        Ok(())
+    }
+
+    /// Forget everything recorded against the given usernames: their
+    /// throttles, and their failed logins along with the addresses and user
+    /// agents those came from
+    ///
+    /// This is for purging deleted accounts, so it returns the database's
+    /// own error, for the caller's transaction. Address throttles stand, as
+    /// they don't belong to any one account.
+    pub fn forget(connection: &Connection, usernames: &[String]) -> QueryResult<()> {
+        use schema::{failed_logins, login_throttles};
+
+        let subjects: Vec<String> = usernames.iter().map(|u| username_subject(u)).collect();
+        delete(login_throttles::table.filter(login_throttles::subject.eq_any(subjects)))
+            .execute(connection)?;
+        let normalized: Vec<String> = usernames.iter().map(|u| normalize(u)).collect();
+        delete(failed_logins::table.filter(
+            lower(failed_logins::username).eq_any(normalized),
+        )).execute(connection)?;
+        Ok(())
+    }
+}
+
+/// How much longer a subject is blocked for, which may be zero or negative
+fn blocked_for(
+    connection: &Connection,
+    incoming_subject: &str,
+    now: NaiveDateTime,
+) -> Result<Duration, &'static str> {
+    use schema::login_throttles::dsl::*;
+
+    let throttle = login_throttles
+        .filter(subject.eq(incoming_subject))
+        .first::<Throttle>(connection)
+        .optional()
+        .map_err(|_| "Failed to look up login throttle")?;
+    Ok(match throttle {
+        Some(throttle) => throttle.blocked_until.signed_duration_since(now),
+        None => Duration::zero(),
+    })
+}
+
+/// Count one more failure against a subject, and block it accordingly
+fn bump(
+    connection: &Connection,
+    incoming_subject: &str,
+    policy: &Policy,
+    now: NaiveDateTime,
+) -> Result<(), &'static str> {
+    use schema::login_throttles::dsl::*;
+
+    let throttle = login_throttles
+        .filter(subject.eq(incoming_subject))
+        .first::<Throttle>(connection)
+        .optional()
+        .map_err(|_| "Failed to look up login throttle")?;
+
+    match throttle {
+        Some(throttle) => {
+            // Failures long enough ago are forgiven, so that a user who
+            // occasionally mistypes their password isn't eventually locked out.
+            let count = if now.signed_duration_since(throttle.last_failure) > *LOGIN_FAILURE_MEMORY {
+                1
+            } else {
+                throttle.failures + 1
+            };
+            update(login_throttles.find(throttle.id))
+                .set((
+                    failures.eq(count),
+                    last_failure.eq(now),
+                    blocked_until.eq(now + policy.backoff(count)),
+                ))
+                .execute(connection)
+                .map_err(|_| "Failed to update login throttle")?;
+        }
+        None => {
+            insert(&NewThrottle {
+                subject: incoming_subject,
+                failures: 1,
+                last_failure: now,
+                blocked_until: now + policy.backoff(1),
+            }).into(login_throttles)
+                .execute(connection)
+                .map_err(|_| "Failed to insert login throttle")?;
+        }
+    }
+    Ok(())
+}
+
+fn audit(
+    connection: &Connection,
+    username: &str,
+    ip: Option<&str>,
+    user_agent: Option<&str>,
+    reason: &str,
+) -> Result<(), &'static str> {
+    use schema::failed_logins;
+
+    insert(&NewFailedLogin {
+        username: username,
+        ip: ip,
+        user_agent: user_agent,
+        reason: reason,
+    }).into(failed_logins::table)
+        .execute(connection)
+        .map_err(|_| "Failed to record failed login")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> Policy {
+        Policy {
+            free_attempts: 3,
+            lockout_threshold: 10,
+            lockout: Duration::minutes(15),
+        }
+    }
+
+    #[test]
+    fn test_free_attempts_have_no_backoff() {
+        let policy = policy();
+        for failures in 0..4 {
+            assert_eq!(policy.backoff(failures), Duration::zero());
+        }
+    }
+
+    #[test]
+    fn test_backoff_doubles() {
+        let policy = policy();
+        assert_eq!(policy.backoff(4), Duration::seconds(1));
+        assert_eq!(policy.backoff(5), Duration::seconds(2));
+        assert_eq!(policy.backoff(6), Duration::seconds(4));
+        assert_eq!(policy.backoff(9), Duration::seconds(32));
+    }
+
+    #[test]
+    fn test_lockout() {
+        let policy = policy();
+        assert_eq!(policy.backoff(10), Duration::minutes(15));
+        assert_eq!(policy.backoff(1000), Duration::minutes(15));
+    }
+
+    #[test]
+    fn test_backoff_never_exceeds_lockout() {
+        let policy = Policy {
+            free_attempts: 0,
+            lockout_threshold: 100,
+            lockout: Duration::minutes(1),
+        };
+        assert_eq!(policy.backoff(99), Duration::minutes(1));
+    }
+}
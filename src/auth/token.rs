@@ -1,21 +1,31 @@
-use diesel::{delete, insert, select};
+use chrono::{NaiveDateTime, Utc};
+use diesel::{delete, insert, select, update};
 use diesel::result::Error as ResultError;
 use diesel::prelude::*;
 use rand::{OsRng, Rng};
+use ring::{constant_time, digest};
 use rocket::http::Status;
 use rocket::request::{Request, FromRequest, Outcome};
 use rocket::outcome::Outcome::*;
 
-use db::CONNECTION_POOL;
+use config::{TOKEN_IDLE_TIMEOUT, TOKEN_LIFETIME};
+use db::{Connection, CONNECTION_POOL};
 use models::{User, Token, NewToken};
 
+/// Content of the `WWW-Authenticate` header sent alongside 401 responses
+pub const WWW_AUTHENTICATE: &'static str = "Token realm=\"sonar\"";
+
 /// Token Authentication
+///
+/// Carries both the authenticated user and the token they presented,
+/// so that views can tell which of the user's sessions is the current one.
 pub struct TokenAuth {
     pub user: User,
+    pub token: Token,
 }
 
 impl TokenAuth {
-    /// Invalidate a user's token
+    /// Invalidate all of a user's tokens
     pub fn invalidate_for(user: &User) -> Result<(), &'static str> {
         use schema::auth_tokens::dsl::*;
 
@@ -30,66 +40,165 @@ impl TokenAuth {
         Ok(())
     }
 
-    /// Create and return a token for the specified user.
+    /// Invalidate all of a user's tokens except the given one
     ///
-    /// - Invalidates any existing user tokens for this user
-    /// - Creates a 64-byte ascii-representable secure random token
-    /// - ensures that the created token is unique
-    /// - inserts the association into the DB for the given user
-    ///
-    /// Returns the created key
-    pub fn create_for(user: &User) -> Result<String, &'static str> {
+    /// Use this to sign a user out everywhere else, leaving the session
+    /// that asked for it intact.
+    pub fn invalidate_others(keep: &Token) -> Result<(), &'static str> {
         use schema::auth_tokens::dsl::*;
-        use diesel::expression::dsl::exists;
 
         let connection = CONNECTION_POOL.get().map_err(
             |_| "Couldn't get connection from pool",
         )?;
 
-        // We need to keep trying random keys until we find an unused one.
-        // Typically we'd expect to find this on the first try, but just in case,
-        // we make 10 attempts. Normally I'd prefer to use a for loop for this kind
-        // of bounded thing, but the simplest way to frame that is to break with a
-        // value, which in this version of rust is only allowed from the loop construct.
-        let new_key = {
-            let mut i = 0;
-            loop {
-                let proposed_key: String = OsRng::new()
-                    .map_err(|_| "Couldn't connect to OS RNG")?
-                    .gen_ascii_chars()
-                    .take(64)
-                    .collect();
-
-                let proposed_key_exists: bool =
-                    select(exists(auth_tokens.filter(key.eq(&proposed_key))))
-                        .get_result(&*connection)
-                        .map_err(|_| "Failed to check for key existence")?;
-                if !proposed_key_exists {
-                    break Ok(proposed_key);
-                }
+        delete(auth_tokens.filter(user_id.eq(keep.user_id)).filter(
+            id.ne(keep.id),
+        )).execute(&*connection)
+            .map_err(|_| "Couldn't delete existing keys")?;
 
-                i += 1;
-                if i >= 10 {
-                    break Err("Couldn't find unused key after 10 tries");
-                }
-            }
-        }?;
+        Ok(())
+    }
 
-        delete(auth_tokens.filter(user_id.eq(user.id)))
-            .execute(&*connection)
-            .map_err(|_| "Couldn't delete existing keys")?;
+    /// Create and return a token for the specified user.
+    ///
+    /// - Creates a secure random selector and secret, both ascii-representable
+    /// - ensures that the created selector is unique
+    /// - inserts the association into the DB for the given user, labeled
+    ///   with the device it was issued to. Only a digest of the secret is stored.
+    ///
+    /// A user may hold any number of tokens at once; existing tokens are
+    /// left alone. Use `invalidate_for` to sign a user out everywhere.
+    ///
+    /// Returns the created key, of the form `{selector}.{secret}`
+    pub fn create_for(
+        user: &User,
+        device_name: &str,
+        user_agent: Option<&str>,
+    ) -> Result<String, &'static str> {
+        use schema::auth_tokens::dsl::*;
+
+        let connection = CONNECTION_POOL.get().map_err(
+            |_| "Couldn't get connection from pool",
+        )?;
+
+        let new_selector = unused_selector(&*connection)?;
+        let secret = random_string(SECRET_LENGTH)?;
 
         insert(&NewToken {
             user_id: user.id,
-            key: &new_key,
+            selector: &new_selector,
+            verifier: &digest_secret(&secret),
+            device_name: device_name,
+            user_agent: user_agent,
         }).into(auth_tokens)
             .execute(&*connection)
             .map_err(|_| "Failed to insert key into auth_tokens")?;
 
-        Ok(new_key)
+        Ok(format!("{}.{}", new_selector, secret))
+    }
+
+    /// Replace the key of the token used for this request with a fresh one.
+    ///
+    /// The old key stops working immediately. Refreshing counts as a use, so
+    /// it resets the idle timeout, but the absolute lifetime still runs from
+    /// when the token was first issued: a session can't be kept alive forever.
+    ///
+    /// Returns the new key
+    pub fn refresh(&self) -> Result<String, &'static str> {
+        use schema::auth_tokens::dsl::*;
+
+        let connection = CONNECTION_POOL.get().map_err(
+            |_| "Couldn't get connection from pool",
+        )?;
+
+        let new_selector = unused_selector(&*connection)?;
+        let secret = random_string(SECRET_LENGTH)?;
+
+        update(auth_tokens.find(self.token.id))
+            .set((
+                selector.eq(&new_selector),
+                verifier.eq(digest_secret(&secret)),
+                last_used.eq(Utc::now().naive_utc()),
+            ))
+            .execute(&*connection)
+            .map_err(|_| "Failed to update key in auth_tokens")?;
+
+        Ok(format!("{}.{}", new_selector, secret))
     }
 }
 
+/// How long is the public part of a key, used to look up its token
+pub const SELECTOR_LENGTH: usize = 16;
+/// How long is the secret part of a key
+pub const SECRET_LENGTH: usize = 64;
+
+/// Generate a secure random string of `[a-zA-Z0-9]`
+pub fn random_string(length: usize) -> Result<String, &'static str> {
+    Ok(
+        OsRng::new()
+            .map_err(|_| "Couldn't connect to OS RNG")?
+            .gen_ascii_chars()
+            .take(length)
+            .collect(),
+    )
+}
+
+/// Split a key of the form `{selector}.{secret}` into its parts
+pub fn split_key(key: &str) -> Option<(&str, &str)> {
+    let index = key.find('.')?;
+    Some((&key[..index], &key[index + 1..]))
+}
+
+/// Compute the value stored in the `verifier` column for a given secret
+///
+/// Secrets are long and random, so unlike passwords they don't need a slow,
+/// salted hash; a plain digest is enough to make a leaked row useless.
+pub fn digest_secret(secret: &str) -> Vec<u8> {
+    digest::digest(&digest::SHA256, secret.as_bytes())
+        .as_ref()
+        .to_vec()
+}
+
+/// Check, in constant time, whether a secret matches a stored verifier
+pub fn verify_secret(verifier: &[u8], secret: &str) -> bool {
+    constant_time::verify_slices_are_equal(verifier, &digest_secret(secret)).is_ok()
+}
+
+/// Generate a random selector which isn't yet in use
+fn unused_selector(connection: &Connection) -> Result<String, &'static str> {
+    use schema::auth_tokens::dsl::*;
+    use diesel::expression::dsl::exists;
+
+    // We need to keep trying random selectors until we find an unused one.
+    // Typically we'd expect to find this on the first try, but just in case,
+    // we make 10 attempts. Normally I'd prefer to use a for loop for this kind
+    // of bounded thing, but the simplest way to frame that is to break with a
+    // value, which in this version of rust is only allowed from the loop construct.
+    let mut i = 0;
+    loop {
+        let proposed_selector = random_string(SELECTOR_LENGTH)?;
+
+        let proposed_selector_exists: bool =
+            select(exists(auth_tokens.filter(selector.eq(&proposed_selector))))
+                .get_result(connection)
+                .map_err(|_| "Failed to check for selector existence")?;
+        if !proposed_selector_exists {
+            break Ok(proposed_selector);
+        }
+
+        i += 1;
+        if i >= 10 {
+            break Err("Couldn't find unused selector after 10 tries");
+        }
+    }
+}
+
+/// Whether a token has outlived either its absolute lifetime or its idle timeout
+fn is_expired(token: &Token, now: NaiveDateTime) -> bool {
+    now.signed_duration_since(token.timestamp) > *TOKEN_LIFETIME ||
+        now.signed_duration_since(token.last_used) > *TOKEN_IDLE_TIMEOUT
+}
+
 macro_rules! try_outcome {
     ($condition:expr; $status:expr) => {
         match $condition {
@@ -112,9 +221,9 @@ impl<'a, 'r> FromRequest<'a, 'r> for TokenAuth {
                 ),
             ));
         }
-        let key = keys[0];
+        let header = keys[0];
         const TOKEN_PREFIX: &'static str = "Token ";
-        if !key.starts_with(TOKEN_PREFIX) {
+        if !header.starts_with(TOKEN_PREFIX) {
             return Failure((
                 Status::Unauthorized,
                 format!(
@@ -123,9 +232,19 @@ impl<'a, 'r> FromRequest<'a, 'r> for TokenAuth {
                 ),
             ));
         }
-        let incoming_key = &key[TOKEN_PREFIX.len()..];
+        let incoming_key = &header[TOKEN_PREFIX.len()..];
+        let invalid = || {
+            Failure((
+                Status::Forbidden,
+                String::from("Token presented was not valid"),
+            ))
+        };
+        let (incoming_selector, incoming_secret) = match split_key(incoming_key) {
+            Some(parts) => parts,
+            None => return invalid(),
+        };
 
-        let user = {
+        let (user, token) = {
             // Create a small scope to minimize the amount of time we monopolize
             // the DB connection
             let connection = try_outcome!(CONNECTION_POOL.get(); Status::InternalServerError);
@@ -133,37 +252,59 @@ impl<'a, 'r> FromRequest<'a, 'r> for TokenAuth {
             let token = {
                 // encapsulate the use of the dsl
                 use schema::auth_tokens::dsl::*;
-                match auth_tokens.filter(key.eq(incoming_key)).first::<Token>(
-                    &*connection,
-                ) {
+                match auth_tokens
+                    .filter(selector.eq(incoming_selector))
+                    .first::<Token>(&*connection) {
                     Ok(token) => token,
                     Err(e) => {
                         return if e == ResultError::NotFound {
-                            Failure((
-                                Status::Forbidden,
-                                String::from("Token presented was not valid"),
-                            ))
+                            invalid()
                         } else {
                             Failure((Status::InternalServerError, e.to_string()))
                         }
                     }
                 }
             };
-            // In the future, we might want to implement token invalidation after some
-            // period of time. If that's desired, we should just compare the current time
-            // to `token.timestamp`; if that's greater than the invalidation period, then
-            // we can return failure. Otherwise, the fact that we found a match for the
-            // specified token means that we've logged in successfully.
+            if !verify_secret(&token.verifier, incoming_secret) {
+                return invalid();
+            }
+            // Finding a match for the specified token means that we've logged in
+            // successfully, unless the token has expired. Expired tokens are no use
+            // to anyone, so we clean them up as we find them.
+            let now = Utc::now().naive_utc();
             {
+                use schema::auth_tokens::dsl::*;
+                if is_expired(&token, now) {
+                    try_outcome!(
+                        delete(auth_tokens.find(token.id)).execute(&*connection);
+                        Status::InternalServerError
+                    );
+                    return Failure((
+                        Status::Unauthorized,
+                        String::from("Token presented has expired"),
+                    ));
+                }
+                try_outcome!(
+                    update(auth_tokens.find(token.id))
+                        .set(last_used.eq(now))
+                        .execute(&*connection);
+                    Status::InternalServerError
+                );
+            }
+            let user = {
                 // encapsulate this DSL also
                 use schema::users::dsl::*;
                 match users.find(token.user_id).first::<User>(&*connection) {
                     Ok(user) => user,
                     Err(e) => return Failure((Status::InternalServerError, e.to_string())),
                 }
-            }
+            };
+            (user, token)
         };
 
-        Success(TokenAuth { user: user })
+        Success(TokenAuth {
+            user: user,
+            token: token,
+        })
     }
 }
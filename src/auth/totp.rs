@@ -0,0 +1,131 @@
+use base32;
+use rand::{OsRng, Rng};
+use ring::{digest, hmac};
+
+/// How many random bytes make up a secret: the RFC 4226 recommendation
+const SECRET_LENGTH: usize = 20;
+/// How long each code is valid for, in seconds
+const STEP_SECONDS: i64 = 30;
+/// How many digits each code has
+const DIGITS: u32 = 6;
+/// How many steps either side of the current one we accept codes from,
+/// to allow for clock drift and slow typists
+const WINDOW: i64 = 1;
+
+/// Generate a new random secret
+pub fn generate_secret() -> Result<Vec<u8>, &'static str> {
+    let mut secret = vec![0; SECRET_LENGTH];
+    OsRng::new()
+        .map_err(|_| "Couldn't connect to OS RNG")?
+        .fill_bytes(&mut secret);
+    Ok(secret)
+}
+
+/// The time step a given unix timestamp falls in
+pub fn step_at(unix_time: i64) -> i64 {
+    unix_time / STEP_SECONDS
+}
+
+/// Compute the code for a given secret and time step, per RFC 4226
+pub fn code_at(secret: &[u8], step: i64) -> u32 {
+    let mut counter = [0u8; 8];
+    for (index, byte) in counter.iter_mut().enumerate() {
+        *byte = (step >> (8 * (7 - index))) as u8;
+    }
+    let key = hmac::SigningKey::new(&digest::SHA1, secret);
+    let mac = hmac::sign(&key, &counter);
+    let mac = mac.as_ref();
+
+    // dynamic truncation
+    let offset = (mac[mac.len() - 1] & 0xf) as usize;
+    let truncated = mac[offset..offset + 4]
+        .iter()
+        .fold(0u32, |acc, &byte| (acc << 8) | byte as u32) & 0x7fff_ffff;
+    truncated % 10_u32.pow(DIGITS)
+}
+
+/// Check a code presented at the given unix time.
+///
+/// Codes from steps up to and including `after` are rejected, so that
+/// each code can only be used once.
+///
+/// Returns the step the code matched, if any.
+pub fn verify(secret: &[u8], code: &str, unix_time: i64, after: Option<i64>) -> Option<i64> {
+    if code.len() != DIGITS as usize || !code.chars().all(|c| c.is_digit(10)) {
+        return None;
+    }
+    let code = code.parse::<u32>().ok()?;
+    let current = step_at(unix_time);
+    (current - WINDOW..current + WINDOW + 1)
+        .filter(|&step| after.map(|after| step > after).unwrap_or(true))
+        .find(|&step| code_at(secret, step) == code)
+}
+
+/// Encode a secret the way authenticator apps expect to have it typed in
+pub fn encode_secret(secret: &[u8]) -> String {
+    base32::encode(base32::Alphabet::RFC4648 { padding: false }, secret)
+}
+
+/// Build the `otpauth://` URI with which authenticator apps enroll a secret
+///
+/// This is usually shown to the user as a QR code.
+pub fn provisioning_uri(secret: &[u8], issuer: &str, account: &str) -> String {
+    format!(
+        "otpauth://totp/{issuer}:{account}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={digits}&period={period}",
+        issuer = percent_encode(issuer),
+        account = percent_encode(account),
+        secret = encode_secret(secret),
+        digits = DIGITS,
+        period = STEP_SECONDS,
+    )
+}
+
+/// Percent-encode everything but unreserved characters, per RFC 3986
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'a'...b'z' | b'A'...b'Z' | b'0'...b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The SHA-1 secret from the RFC 6238 test vectors
+    const SECRET: &'static [u8] = b"12345678901234567890";
+
+    #[test]
+    fn test_rfc6238_vectors() {
+        // The RFC lists 8-digit codes; ours are their last 6 digits.
+        assert_eq!(code_at(SECRET, step_at(59)), 287082);
+        assert_eq!(code_at(SECRET, step_at(1111111109)), 81804);
+        assert_eq!(code_at(SECRET, step_at(1111111111)), 50471);
+        assert_eq!(code_at(SECRET, step_at(1234567890)), 5924);
+        assert_eq!(code_at(SECRET, step_at(2000000000)), 279037);
+    }
+
+    #[test]
+    fn test_verify() {
+        let now = 1111111109;
+        assert_eq!(verify(SECRET, "081804", now, None), Some(step_at(now)));
+        assert_eq!(verify(SECRET, "081804", now + 30, None), Some(step_at(now)));
+        assert_eq!(verify(SECRET, "081804", now + 90, None), None);
+        assert_eq!(verify(SECRET, "81804", now, None), None);
+        assert_eq!(verify(SECRET, "081804", now, Some(step_at(now))), None);
+    }
+
+    #[test]
+    fn test_provisioning_uri() {
+        assert_eq!(
+            provisioning_uri(SECRET, "sonar", "some one"),
+            "otpauth://totp/sonar:some%20one?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ\
+             &issuer=sonar&algorithm=SHA1&digits=6&period=30"
+        );
+    }
+}
@@ -0,0 +1,379 @@
+use chrono::{Duration, Utc};
+use diesel::{delete, insert, update};
+use diesel::prelude::*;
+
+use auth::throttle::LoginThrottle;
+use auth::token::{digest_secret, random_string, split_key, verify_secret, SECRET_LENGTH,
+                  SELECTOR_LENGTH};
+use auth::totp;
+use config::LOGIN_CHALLENGE_LIFETIME;
+use db::{Connection, CONNECTION_POOL};
+use models::{User, RecoveryCode, NewRecoveryCode, LoginChallengeToken, NewLoginChallengeToken};
+
+/// The issuer name shown in authenticator apps
+const ISSUER: &'static str = "sonar";
+/// How many recovery codes a user gets on enrolling
+const RECOVERY_CODE_COUNT: usize = 10;
+/// How long is each recovery code
+const RECOVERY_CODE_LENGTH: usize = 12;
+/// How many codes may be tried against a single login challenge
+const MAX_CHALLENGE_ATTEMPTS: i32 = 5;
+
+/// Two-Factor Authentication
+///
+/// Users may enroll a TOTP secret (RFC 6238), after which signing in needs
+/// a code from their authenticator as well as their password. On enrolling,
+/// they're also given single-use recovery codes, which can stand in for an
+/// authenticator code if they lose their device.
+pub struct TwoFactor;
+
+impl TwoFactor {
+    /// Start enrolling a user, by generating a new secret for them.
+    ///
+    /// Two-factor authentication isn't in force until the user proves they've
+    /// set up their authenticator by confirming a code with `confirm_enrollment`.
+    ///
+    /// Returns the new secret, encoded for typing into an authenticator,
+    /// and the `otpauth://` URI for it.
+    pub fn begin_enrollment(user: &User) -> Result<(String, String), &'static str> {
+        use schema::users::dsl::*;
+
+        let connection = CONNECTION_POOL.get().map_err(
+            |_| "Couldn't get connection from pool",
+        )?;
+
+        let secret = totp::generate_secret()?;
+        update(users.find(user.id))
+            .set((
+                totp_secret.eq(Some(&secret)),
+                totp_enabled_at.eq(None),
+                totp_last_step.eq(None),
+            ))
+            .execute(&*connection)
+            .map_err(|_| "Failed to store TOTP secret")?;
+
+        Ok((
+            totp::encode_secret(&secret),
+            totp::provisioning_uri(&secret, ISSUER, &user.username),
+        ))
+    }
+
+    /// Finish enrolling a user, given a code from their authenticator.
+    ///
+    /// Returns the user's new recovery codes, or `None` if the code was wrong.
+    pub fn confirm_enrollment(user: &User, code: &str) -> Result<Option<Vec<String>>, &'static str> {
+        let connection = CONNECTION_POOL.get().map_err(
+            |_| "Couldn't get connection from pool",
+        )?;
+
+        if !check_totp(&*connection, user, code)? {
+            return Ok(None);
+        }
+
+        {
+            use schema::users::dsl::*;
+            update(users.find(user.id))
+                .set(totp_enabled_at.eq(Some(Utc::now().naive_utc())))
+                .execute(&*connection)
+                .map_err(|_| "Failed to enable two-factor authentication")?;
+        }
+
+        use schema::recovery_codes::dsl::*;
+        delete(recovery_codes.filter(user_id.eq(user.id)))
+            .execute(&*connection)
+            .map_err(|_| "Couldn't delete existing recovery codes")?;
+        let mut codes = Vec::with_capacity(RECOVERY_CODE_COUNT);
+        for _ in 0..RECOVERY_CODE_COUNT {
+            let code = random_string(RECOVERY_CODE_LENGTH)?;
+            insert(&NewRecoveryCode {
+                user_id: user.id,
+                verifier: &digest_secret(&code),
+            }).into(recovery_codes)
+                .execute(&*connection)
+                .map_err(|_| "Failed to insert recovery code")?;
+            codes.push(code);
+        }
+        Ok(Some(codes))
+    }
+
+    /// Turn off two-factor authentication for a user, and forget their secret
+    /// and recovery codes.
+    pub fn disable(user: &User) -> Result<(), &'static str> {
+        let connection = CONNECTION_POOL.get().map_err(
+            |_| "Couldn't get connection from pool",
+        )?;
+
+        {
+            use schema::recovery_codes::dsl::*;
+            delete(recovery_codes.filter(user_id.eq(user.id)))
+                .execute(&*connection)
+                .map_err(|_| "Couldn't delete recovery codes")?;
+        }
+
+        use schema::users::dsl::*;
+        update(users.find(user.id))
+            .set((
+                totp_secret.eq(None::<Vec<u8>>),
+                totp_enabled_at.eq(None),
+                totp_last_step.eq(None),
+            ))
+            .execute(&*connection)
+            .map_err(|_| "Failed to disable two-factor authentication")?;
+        Ok(())
+    }
+
+    /// Check a second-factor code for a user who has two-factor authentication enabled.
+    ///
+    /// The code may come from their authenticator, or be one of their recovery
+    /// codes. Either way, it's used up, and won't work again.
+    pub fn check(user: &User, code: &str) -> Result<bool, &'static str> {
+        let connection = CONNECTION_POOL.get().map_err(
+            |_| "Couldn't get connection from pool",
+        )?;
+        check_code(&*connection, user, code)
+    }
+}
+
+/// Check a code from the user's authenticator, and record its time step so it can't be reused
+fn check_totp(connection: &Connection, user: &User, code: &str) -> Result<bool, &'static str> {
+    use schema::users::dsl::*;
+
+    let secret = match user.totp_secret {
+        Some(ref secret) => secret,
+        None => return Ok(false),
+    };
+    let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+    let after = user.totp_last_step.map(|step| step as i64);
+    match totp::verify(secret, &code, Utc::now().timestamp(), after) {
+        Some(step) => {
+            update(users.find(user.id))
+                .set(totp_last_step.eq(Some(step as i32)))
+                .execute(connection)
+                .map_err(|_| "Failed to record TOTP use")?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+fn check_code(connection: &Connection, user: &User, code: &str) -> Result<bool, &'static str> {
+    use schema::recovery_codes::dsl::*;
+
+    if !user.two_factor_enabled() {
+        return Ok(false);
+    }
+    if check_totp(connection, user, code)? {
+        return Ok(true);
+    }
+
+    let candidates = recovery_codes
+        .filter(user_id.eq(user.id))
+        .load::<RecoveryCode>(connection)
+        .map_err(|_| "Failed to look up recovery codes")?;
+    // Check every candidate, rather than stopping at the first match, so that
+    // timing doesn't reveal anything about which codes exist.
+    let mut matched = None;
+    for candidate in candidates.iter() {
+        if verify_secret(&candidate.verifier, code.trim()) {
+            matched = Some(candidate.id);
+        }
+    }
+    match matched {
+        Some(matched_id) => {
+            delete(recovery_codes.find(matched_id))
+                .execute(connection)
+                .map_err(|_| "Couldn't delete used recovery code")?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+/// The outcome of answering a login challenge
+pub enum Answer {
+    /// The code was right; here are the challenged user and the challenge itself
+    Accepted(User, LoginChallengeToken),
+    /// The challenge wasn't valid, has expired, or the code was wrong
+    Rejected,
+    /// The user, or the client's address, has failed to log in too often;
+    /// nothing was checked, and the client must wait this long
+    Throttled(Duration),
+}
+
+/// Login Challenge
+///
+/// When a user with two-factor authentication enabled passes the password
+/// check, they get a challenge rather than an auth token. They exchange the
+/// challenge, together with a second-factor code, for the token. Challenges
+/// expire after `LOGIN_CHALLENGE_LIFETIME`, and only a few codes may be
+/// tried against each.
+///
+/// A wrong code is a failed login like a wrong password, and is throttled in
+/// the same way; otherwise anyone who knew the password could keep asking
+/// for fresh challenges and guess codes without limit.
+pub struct LoginChallenge;
+
+impl LoginChallenge {
+    /// Create and return a challenge for the specified user, remembering
+    /// which device the eventual token should be labeled with.
+    ///
+    /// Returns the created key, of the form `{selector}.{secret}`
+    pub fn create_for(
+        connection: &Connection,
+        user: &User,
+        device_name: &str,
+        user_agent: Option<&str>,
+    ) -> Result<String, &'static str> {
+        use schema::login_challenges::dsl::*;
+
+        let new_selector = random_string(SELECTOR_LENGTH)?;
+        let secret = random_string(SECRET_LENGTH)?;
+
+        insert(&NewLoginChallengeToken {
+            user_id: user.id,
+            selector: &new_selector,
+            verifier: &digest_secret(&secret),
+            device_name: device_name,
+            user_agent: user_agent,
+        }).into(login_challenges)
+            .execute(connection)
+            .map_err(|_| "Failed to insert login challenge")?;
+
+        Ok(format!("{}.{}", new_selector, secret))
+    }
+
+    /// Answer a challenge with a second-factor code, from the given address
+    /// and user agent.
+    ///
+    /// A challenge can only be answered correctly once. The caller should
+    /// record the login's success with `LoginThrottle` once it issues a token.
+    pub fn answer(
+        connection: &Connection,
+        incoming_key: &str,
+        code: &str,
+        ip: Option<&str>,
+        user_agent: Option<&str>,
+    ) -> Result<Answer, &'static str> {
+        use schema::login_challenges::dsl::*;
+
+        let (incoming_selector, incoming_secret) = match split_key(incoming_key) {
+            Some(parts) => parts,
+            None => return Ok(Answer::Rejected),
+        };
+        let challenge = match login_challenges
+            .filter(selector.eq(incoming_selector))
+            .first::<LoginChallengeToken>(connection)
+            .optional()
+            .map_err(|_| "Failed to look up login challenge")? {
+            Some(challenge) => challenge,
+            None => return Ok(Answer::Rejected),
+        };
+        if !verify_secret(&challenge.verifier, incoming_secret) {
+            return Ok(Answer::Rejected);
+        }
+
+        let user = {
+            use schema::users::dsl::*;
+            users
+                .find(challenge.user_id)
+                .first::<User>(connection)
+                .map_err(|_| "Failed to look up user")?
+        };
+        if let Some(wait) = LoginThrottle::retry_after(connection, &user.username, ip)? {
+            // The audit trail is a nice-to-have; refusing the attempt is what matters.
+            let _ = LoginThrottle::record_refusal(connection, &user.username, ip, user_agent);
+            return Ok(Answer::Throttled(wait));
+        }
+
+        let expired = Utc::now()
+            .naive_utc()
+            .signed_duration_since(challenge.timestamp) > *LOGIN_CHALLENGE_LIFETIME;
+        // Take an attempt before checking the code, in a single statement, so
+        // that answers sent in parallel can't all see the same count.
+        let counted = if expired {
+            0
+        } else {
+            update(
+                login_challenges
+                    .filter(id.eq(challenge.id))
+                    .filter(attempts.lt(MAX_CHALLENGE_ATTEMPTS)),
+            ).set(attempts.eq(attempts + 1))
+                .execute(connection)
+                .map_err(|_| "Failed to record login challenge attempt")?
+        };
+        if counted == 0 {
+            delete(login_challenges.find(challenge.id))
+                .execute(connection)
+                .map_err(|_| "Couldn't delete login challenge")?;
+            return Ok(Answer::Rejected);
+        }
+
+        if !check_code(connection, &user, code)? {
+            LoginThrottle::record_failure(connection, &user.username, ip, user_agent)?;
+            return Ok(Answer::Rejected);
+        }
+        // Only whoever deletes the challenge may use it, should two right
+        // answers arrive at once
+        let deleted = delete(login_challenges.find(challenge.id))
+            .execute(connection)
+            .map_err(|_| "Couldn't delete login challenge")?;
+        Ok(if deleted == 1 {
+            Answer::Accepted(user, challenge)
+        } else {
+            Answer::Rejected
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::LOGIN_USERNAME_FREE_ATTEMPTS;
+    use db::establish_test_connection;
+    use models::NewUser;
+
+    #[test]
+    fn test_wrong_codes_count_towards_lockout() {
+        let connection = establish_test_connection();
+        let user = NewUser::new(
+            String::from("challenged"),
+            String::from("correct horse battery staple"),
+            String::new(),
+            String::new(),
+            None,
+        ).insert(&connection)
+            .unwrap();
+        {
+            use schema::users::dsl::*;
+            update(users.find(user.id))
+                .set((
+                    totp_secret.eq(Some(totp::generate_secret().unwrap())),
+                    totp_enabled_at.eq(Some(Utc::now().naive_utc())),
+                ))
+                .execute(&connection)
+                .unwrap();
+        }
+        let ip = Some("192.0.2.1");
+
+        // Each wrong code counts, even though every one is sent to a fresh
+        // challenge, as it would be by someone who knows the password
+        for _ in 0..*LOGIN_USERNAME_FREE_ATTEMPTS + 1 {
+            let key = LoginChallenge::create_for(&connection, &user, "", None).unwrap();
+            match LoginChallenge::answer(&connection, &key, "not a code", ip, None).unwrap() {
+                Answer::Rejected => {}
+                _ => panic!("a wrong code was not rejected"),
+            }
+        }
+
+        let key = LoginChallenge::create_for(&connection, &user, "", None).unwrap();
+        match LoginChallenge::answer(&connection, &key, "not a code", ip, None).unwrap() {
+            Answer::Throttled(_) => {}
+            _ => panic!("wrong codes did not throttle the user"),
+        }
+        assert!(
+            LoginThrottle::retry_after(&connection, "challenged", None)
+                .unwrap()
+                .is_some()
+        );
+    }
+}
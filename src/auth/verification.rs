@@ -0,0 +1,100 @@
+use chrono::Utc;
+use diesel::{delete, insert, update};
+use diesel::prelude::*;
+
+use auth::token::{digest_secret, random_string, split_key, verify_secret, SECRET_LENGTH,
+                  SELECTOR_LENGTH};
+use config::EMAIL_VERIFICATION_LIFETIME;
+use db::CONNECTION_POOL;
+use models::{User, EmailVerificationToken, NewEmailVerificationToken};
+
+/// Email Verification
+///
+/// To prove that an email address is theirs, a user is sent a token at that
+/// address, which they then present back to us. Tokens can only be used once,
+/// expire after `EMAIL_VERIFICATION_LIFETIME`, and only verify the address they
+/// were sent to. Only a digest of each token's secret is stored.
+pub struct EmailVerification;
+
+impl EmailVerification {
+    /// Create and return a verification token for the user's current email address.
+    ///
+    /// Any verification tokens previously issued to this user stop working.
+    ///
+    /// Returns the created key, of the form `{selector}.{secret}`
+    pub fn create_for(user: &User) -> Result<String, &'static str> {
+        use schema::email_verification_tokens::dsl::*;
+
+        let address = user.email.as_ref().ok_or("User has no email address")?;
+        let connection = CONNECTION_POOL.get().map_err(
+            |_| "Couldn't get connection from pool",
+        )?;
+
+        let new_selector = random_string(SELECTOR_LENGTH)?;
+        let secret = random_string(SECRET_LENGTH)?;
+
+        delete(email_verification_tokens.filter(user_id.eq(user.id)))
+            .execute(&*connection)
+            .map_err(|_| "Couldn't delete existing verification tokens")?;
+
+        insert(&NewEmailVerificationToken {
+            user_id: user.id,
+            selector: &new_selector,
+            verifier: &digest_secret(&secret),
+            email: address,
+        }).into(email_verification_tokens)
+            .execute(&*connection)
+            .map_err(|_| "Failed to insert verification token")?;
+
+        Ok(format!("{}.{}", new_selector, secret))
+    }
+
+    /// Use up a verification token issued to the given user.
+    ///
+    /// If the key is valid, hasn't expired, and was sent to the user's current
+    /// email address, that address is marked as verified and this returns `true`.
+    /// Either way, the key won't work again.
+    pub fn redeem(user: &User, incoming_key: &str) -> Result<bool, &'static str> {
+        use schema::email_verification_tokens::dsl::*;
+
+        let connection = CONNECTION_POOL.get().map_err(
+            |_| "Couldn't get connection from pool",
+        )?;
+
+        let (incoming_selector, incoming_secret) = match split_key(incoming_key) {
+            Some(parts) => parts,
+            None => return Ok(false),
+        };
+        let token = match email_verification_tokens
+            .filter(selector.eq(incoming_selector))
+            .filter(user_id.eq(user.id))
+            .first::<EmailVerificationToken>(&*connection)
+            .optional()
+            .map_err(|_| "Failed to look up verification token")? {
+            Some(token) => token,
+            None => return Ok(false),
+        };
+        if !verify_secret(&token.verifier, incoming_secret) {
+            return Ok(false);
+        }
+
+        delete(email_verification_tokens.find(token.id))
+            .execute(&*connection)
+            .map_err(|_| "Couldn't delete used verification token")?;
+        let now = Utc::now().naive_utc();
+        if now.signed_duration_since(token.timestamp) > *EMAIL_VERIFICATION_LIFETIME ||
+            user.email.as_ref() != Some(&token.email)
+        {
+            return Ok(false);
+        }
+
+        {
+            use schema::users::dsl::*;
+            update(users.find(user.id))
+                .set(email_verified_at.eq(Some(now)))
+                .execute(&*connection)
+                .map_err(|_| "Failed to mark email as verified")?;
+        }
+        Ok(true)
+    }
+}
@@ -0,0 +1,114 @@
+//! Storage for uploaded files.
+//!
+//! Everything which stores files does so through the `BlobStore` trait, so
+//! that the files can live on local disk in development and somewhere more
+//! durable in production. The store in use is chosen by the `BLOB_STORE`
+//! setting; see `config`.
+
+use config::{BLOB_BASE_URL, BLOB_DIR, BLOB_STORE_KIND};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+
+lazy_static! {
+    /// The blob store configured by the `BLOB_STORE` setting
+    pub static ref BLOB_STORE: Box<BlobStore + Send + Sync> = match BLOB_STORE_KIND.as_str() {
+        "local" => Box::new(LocalBlobStore {
+            dir: PathBuf::from(&*BLOB_DIR),
+            base_url: BLOB_BASE_URL.clone(),
+        }),
+        other => panic!("Unknown BLOB_STORE: {}", other),
+    };
+}
+
+/// Something which can store files and say where to fetch them from
+///
+/// Keys are relative paths made of `[a-zA-Z0-9_.-]` segments separated by `/`.
+pub trait BlobStore {
+    /// Store a file under the given key, replacing anything already there
+    fn put(&self, key: &str, data: &[u8]) -> Result<(), String>;
+
+    /// Fetch the file stored under the given key
+    fn get(&self, key: &str) -> Result<Vec<u8>, String>;
+
+    /// Remove the file with the given key; removing a missing file is not an error
+    fn delete(&self, key: &str) -> Result<(), String>;
+
+    /// The URL from which clients can fetch the file with the given key
+    fn url(&self, key: &str) -> String;
+}
+
+/// Check that a key can't escape the store it's used with
+fn is_valid_key(key: &str) -> bool {
+    !key.is_empty() &&
+        key.split('/').all(|segment| {
+            !segment.is_empty() && segment != "." && segment != ".." &&
+                segment.chars().all(|c| {
+                    c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '-'
+                })
+        })
+}
+
+/// Stores files in a directory on local disk
+///
+/// The files are served by the `media` view, at `base_url`.
+pub struct LocalBlobStore {
+    pub dir: PathBuf,
+    pub base_url: String,
+}
+
+impl LocalBlobStore {
+    fn path(&self, key: &str) -> Result<PathBuf, String> {
+        if is_valid_key(key) {
+            Ok(self.dir.join(key))
+        } else {
+            Err(format!("Invalid blob key: {}", key))
+        }
+    }
+}
+
+impl BlobStore for LocalBlobStore {
+    fn put(&self, key: &str, data: &[u8]) -> Result<(), String> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let mut file = File::create(path).map_err(|e| e.to_string())?;
+        file.write_all(data).map_err(|e| e.to_string())
+    }
+
+    fn get(&self, key: &str) -> Result<Vec<u8>, String> {
+        let mut data = Vec::new();
+        File::open(self.path(key)?)
+            .and_then(|mut file| file.read_to_end(&mut data))
+            .map_err(|e| e.to_string())?;
+        Ok(data)
+    }
+
+    fn delete(&self, key: &str) -> Result<(), String> {
+        match fs::remove_file(self.path(key)?) {
+            Ok(()) => Ok(()),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    fn url(&self, key: &str) -> String {
+        format!("{}/{}", self.base_url.trim_right_matches('/'), key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid_keys() {
+        assert!(is_valid_key("avatar/12/AbCd-48.png"));
+        assert!(!is_valid_key(""));
+        assert!(!is_valid_key("/etc/passwd"));
+        assert!(!is_valid_key("avatar/../../etc/passwd"));
+        assert!(!is_valid_key("avatar//12"));
+        assert!(!is_valid_key("avatar/12/a b.png"));
+    }
+}
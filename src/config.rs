@@ -0,0 +1,205 @@
+//! Runtime configuration.
+//!
+//! Like `DATABASE_URL`, everything here is read from the environment
+//! (or a `.env` file) the first time it's used. Unlike `DATABASE_URL`,
+//! every setting has a sensible default, so none of them need be set.
+
+use base64;
+use chrono::Duration;
+use dotenv::dotenv;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::str::FromStr;
+
+/// Read and parse an environment variable, falling back to `default` if it's unset.
+///
+/// Panics if the variable is set but can't be parsed; a typo in the configuration
+/// shouldn't silently turn into the default.
+fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    dotenv().ok();
+    match env::var(name) {
+        Ok(value) => value.parse().ok().expect(&format!("{} is malformed", name)),
+        Err(_) => default,
+    }
+}
+
+lazy_static! {
+    /// Memory cost for newly hashed passwords, in KiB
+    ///
+    /// The argon2 defaults here follow the OWASP recommendations, and are modest
+    /// enough to run on small machines. Raising any of them causes existing
+    /// passwords to be rehashed the next time their owners log in.
+    pub static ref ARGON2_MEMORY_KIB: u32 = env_or("ARGON2_MEMORY_KIB", 19456);
+
+    /// Time cost for newly hashed passwords: the number of passes over memory
+    pub static ref ARGON2_ITERATIONS: u32 = env_or("ARGON2_ITERATIONS", 2);
+
+    /// Degree of parallelism for newly hashed passwords
+    pub static ref ARGON2_PARALLELISM: u32 = env_or("ARGON2_PARALLELISM", 1);
+
+    /// Minimum length of new passwords, in characters
+    pub static ref PASSWORD_MIN_LENGTH: usize = env_or("PASSWORD_MIN_LENGTH", 16);
+
+    /// Minimum estimated entropy of new passwords, in bits
+    pub static ref PASSWORD_MIN_ENTROPY_BITS: f64 = env_or("PASSWORD_MIN_ENTROPY_BITS", 40.0);
+
+    /// Names nobody may sign up with, as they belong to the site itself
+    ///
+    /// Set as `RESERVED_USERNAMES=name1,name2`; this replaces the defaults.
+    /// Names which merely look like these are reserved too.
+    pub static ref RESERVED_USERNAMES: HashSet<String> = env_or(
+        "RESERVED_USERNAMES",
+        String::from(
+            "admin,administrator,root,system,staff,support,help,security,moderator,\
+             sonar,api,v1,settings,about,login,logout,signup,me,users,sessions,pings,\
+             password_resets,null,undefined",
+        ),
+    ).split(',')
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .collect();
+
+    /// Secret keys with which passwords may be peppered, by key id
+    ///
+    /// Set as `PASSWORD_PEPPERS=id1:base64key1,id2:base64key2`; ids may only
+    /// contain `[a-zA-Z0-9]`. Keep old keys listed after rotating away from them:
+    /// a password peppered with a key that's no longer configured can't be validated.
+    pub static ref PASSWORD_PEPPERS: HashMap<String, Vec<u8>> = {
+        dotenv().ok();
+        let peppers = env::var("PASSWORD_PEPPERS").unwrap_or_default();
+        peppers
+            .split(',')
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let split_index = entry.find(':').expect("PASSWORD_PEPPERS entries must be id:key");
+                let (id, key) = entry.split_at(split_index);
+                assert!(
+                    !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric()),
+                    "PASSWORD_PEPPERS ids may only contain [a-zA-Z0-9]"
+                );
+                let key = base64::decode(&key[1..]).expect("PASSWORD_PEPPERS keys must be base64");
+                (id.to_string(), key)
+            })
+            .collect()
+    };
+
+    /// The id of the pepper applied to newly hashed passwords
+    ///
+    /// If unset, new passwords aren't peppered. Changing it causes existing
+    /// passwords to be rehashed with the new pepper the next time their owners log in.
+    pub static ref PASSWORD_PEPPER_ID: Option<String> = {
+        dotenv().ok();
+        let id = env::var("PASSWORD_PEPPER_ID").ok();
+        if let Some(ref id) = id {
+            assert!(
+                PASSWORD_PEPPERS.contains_key(id),
+                "PASSWORD_PEPPER_ID must name one of the PASSWORD_PEPPERS"
+            );
+        }
+        id
+    };
+
+    /// How long a password reset token remains valid after it is issued
+    pub static ref PASSWORD_RESET_LIFETIME: Duration =
+        Duration::minutes(env_or("PASSWORD_RESET_LIFETIME_MINUTES", 60));
+
+    /// How long an email verification token remains valid after it is issued
+    pub static ref EMAIL_VERIFICATION_LIFETIME: Duration =
+        Duration::hours(env_or("EMAIL_VERIFICATION_LIFETIME_HOURS", 48));
+
+    /// Which mailer to send email with: one of `smtp`, `file`, or `stdout`
+    pub static ref MAILER_KIND: String = env_or("MAILER", String::from("stdout"));
+
+    /// The address email is sent from
+    pub static ref MAIL_FROM: String = env_or("MAIL_FROM", String::from("sonar@localhost"));
+
+    /// Where the `file` mailer writes its messages
+    pub static ref MAIL_DIR: String = env_or("MAIL_DIR", String::from("mail"));
+
+    /// The SMTP server the `smtp` mailer relays through
+    pub static ref SMTP_HOST: String = env_or("SMTP_HOST", String::from("localhost"));
+
+    /// Credentials for the SMTP server, if it needs them
+    pub static ref SMTP_USERNAME: String = env_or("SMTP_USERNAME", String::new());
+    pub static ref SMTP_PASSWORD: String = env_or("SMTP_PASSWORD", String::new());
+
+    /// How long a token remains valid after it is issued, no matter how
+    /// often it is used or refreshed
+    pub static ref TOKEN_LIFETIME: Duration = Duration::days(env_or("TOKEN_LIFETIME_DAYS", 30));
+
+    /// How long a token may go unused before it expires
+    pub static ref TOKEN_IDLE_TIMEOUT: Duration =
+        Duration::hours(env_or("TOKEN_IDLE_TIMEOUT_HOURS", 24 * 7));
+
+    /// How long a user has to answer a two-factor login challenge
+    pub static ref LOGIN_CHALLENGE_LIFETIME: Duration =
+        Duration::minutes(env_or("LOGIN_CHALLENGE_LIFETIME_MINUTES", 5));
+
+    /// How many failed logins against one username are allowed before backoff starts
+    pub static ref LOGIN_USERNAME_FREE_ATTEMPTS: i32 = env_or("LOGIN_USERNAME_FREE_ATTEMPTS", 3);
+
+    /// How many failed logins against one username lock it out
+    pub static ref LOGIN_USERNAME_LOCKOUT_THRESHOLD: i32 =
+        env_or("LOGIN_USERNAME_LOCKOUT_THRESHOLD", 10);
+
+    /// How many failed logins from one address are allowed before backoff starts
+    ///
+    /// This is more lenient than the per-username limit, as many users may
+    /// share an address.
+    pub static ref LOGIN_IP_FREE_ATTEMPTS: i32 = env_or("LOGIN_IP_FREE_ATTEMPTS", 20);
+
+    /// How many failed logins from one address lock it out
+    pub static ref LOGIN_IP_LOCKOUT_THRESHOLD: i32 = env_or("LOGIN_IP_LOCKOUT_THRESHOLD", 100);
+
+    /// How long a lockout lasts
+    pub static ref LOGIN_LOCKOUT_DURATION: Duration =
+        Duration::minutes(env_or("LOGIN_LOCKOUT_MINUTES", 15));
+
+    /// How long after the last failed login its count is forgotten
+    pub static ref LOGIN_FAILURE_MEMORY: Duration =
+        Duration::hours(env_or("LOGIN_FAILURE_MEMORY_HOURS", 24));
+
+    /// How long after a user changes their username the old one redirects
+    /// to them, and can't be claimed by anyone else
+    pub static ref USERNAME_GRACE_PERIOD: Duration =
+        Duration::days(env_or("USERNAME_GRACE_PERIOD_DAYS", 30));
+
+    /// How long a deleted account lingers, deactivated, before it's purged;
+    /// signing in during this time cancels the deletion
+    pub static ref ACCOUNT_DELETION_GRACE_PERIOD: Duration =
+        Duration::days(env_or("ACCOUNT_DELETION_GRACE_PERIOD_DAYS", 30));
+
+    /// How often to look for deleted accounts to purge
+    pub static ref ACCOUNT_PURGE_INTERVAL: Duration =
+        Duration::minutes(env_or("ACCOUNT_PURGE_INTERVAL_MINUTES", 60));
+
+    /// Where personal data export archives are kept
+    ///
+    /// Unlike the blob store, this must not be served publicly: archives are
+    /// only handed out to their owners.
+    pub static ref EXPORT_DIR: String = env_or("EXPORT_DIR", String::from("exports"));
+
+    /// How long a finished export may be downloaded for
+    pub static ref EXPORT_LIFETIME: Duration = Duration::days(env_or("EXPORT_LIFETIME_DAYS", 7));
+
+    /// Where uploaded files are stored: currently only `local`
+    pub static ref BLOB_STORE_KIND: String = env_or("BLOB_STORE", String::from("local"));
+
+    /// Where the `local` blob store keeps its files
+    pub static ref BLOB_DIR: String = env_or("BLOB_DIR", String::from("media"));
+
+    /// The URL under which the `local` blob store's files are served
+    pub static ref BLOB_BASE_URL: String = env_or("BLOB_BASE_URL", String::from("/media"));
+
+    /// The largest image upload accepted, in bytes
+    pub static ref IMAGE_MAX_BYTES: u64 = env_or("IMAGE_MAX_BYTES", 5 * 1024 * 1024);
+
+    /// The most pixels an uploaded image may have; a photo of 6000 by 4000 fits
+    ///
+    /// Compressed images can be tiny on the wire but vast once decoded, so the
+    /// byte limit alone doesn't bound the memory an upload needs.
+    pub static ref IMAGE_MAX_PIXELS: u64 = env_or("IMAGE_MAX_PIXELS", 25_000_000);
+
+    /// How many items each page of a paginated list holds
+    pub static ref PAGE_SIZE: usize = env_or("PAGE_SIZE", 20);
+}
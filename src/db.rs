@@ -29,6 +29,25 @@ pub fn establish_connection() -> Connection {
     Connection::establish(&DATABASE_URL).expect(&format!("Error connecting to {}", *DATABASE_URL))
 }
 
+/// Establish a connection to a new, fully migrated database in memory
+///
+/// Each connection gets a database of its own, so tests can't interfere
+/// with one another.
+#[cfg(test)]
+pub fn establish_test_connection() -> Connection {
+    use diesel::migrations::run_pending_migrations_in_directory;
+    use std::io;
+    use std::path::Path;
+
+    let connection = Connection::establish(":memory:").expect("Error creating test database");
+    run_pending_migrations_in_directory(
+        &connection,
+        &Path::new(env!("CARGO_MANIFEST_DIR")).join("migrations"),
+        &mut io::sink(),
+    ).expect("Error migrating test database");
+    connection
+}
+
 pub fn create_connection_pool() -> ConnectionPool {
     let config = Config::default();
     let manager = ConnectionManager::<Connection>::new(DATABASE_URL.clone());
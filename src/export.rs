@@ -0,0 +1,491 @@
+//! Personal data export archives.
+//!
+//! A user may ask for a copy of everything we hold about them. Building it
+//! can take a while, so it happens on a background thread: the request only
+//! records a pending export, which becomes ready once its archive has been
+//! written to `EXPORT_DIR`.
+//!
+//! Each archive is a zip file holding the user's data as JSON, their images,
+//! and an `index.html` which presents it all for people rather than programs.
+//! Likes are only counted on pings for now, so they appear as those counts.
+//!
+//! Archives are written straight to disk as they're built, one file at a
+//! time, and pings a batch at a time, so that a large one never has to fit
+//! in memory. Pings are listed newest first, as they are everywhere else.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Seek, Write};
+use std::path::PathBuf;
+use std::thread;
+
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel;
+use diesel::prelude::*;
+use diesel::result::QueryResult;
+use serde_json;
+use zip::{CompressionMethod, ZipWriter};
+use zip::write::FileOptions;
+
+use auth::token::random_string;
+use blob::BLOB_STORE;
+use config::{EXPORT_DIR, EXPORT_LIFETIME};
+use db::{Connection, CONNECTION_POOL};
+use media::{ImageKind, SIZE_PLACEHOLDER};
+use models::{DataExport, NewDataExport, Ping, User};
+use page::Position;
+use schema::{users, follows, blocks, data_exports};
+
+/// Status of an export whose archive is still being built
+pub const STATUS_PENDING: &'static str = "pending";
+/// Status of an export whose archive can be downloaded
+pub const STATUS_READY: &'static str = "ready";
+/// Status of an export whose archive couldn't be built
+pub const STATUS_FAILED: &'static str = "failed";
+
+/// How long an export may stay pending before we assume its build died
+const BUILD_TIMEOUT_MINUTES: i64 = 60;
+
+/// How long is the random part of an archive's file name
+const FILE_NONCE_LENGTH: usize = 16;
+
+/// How many pings are fetched at once while writing an archive
+const PING_BATCH_SIZE: usize = 500;
+
+/// Start building a new export of the user's data
+///
+/// Returns `None` if an export is already being built for them.
+pub fn request(user: &User) -> Result<Option<DataExport>, &'static str> {
+    let connection = CONNECTION_POOL.get().map_err(
+        |_| "Couldn't get connection from pool",
+    )?;
+
+    let previous = latest(&*connection, user).map_err(
+        |_| "Failed to look up previous exports",
+    )?;
+    if let Some(previous) = previous {
+        let timeout = Utc::now().naive_utc() - Duration::minutes(BUILD_TIMEOUT_MINUTES);
+        if previous.status == STATUS_PENDING && previous.requested_at > timeout {
+            return Ok(None);
+        }
+    }
+
+    diesel::insert(&NewDataExport { user_id: user.id })
+        .into(data_exports::table)
+        .execute(&*connection)
+        .map_err(|_| "Failed to record export")?;
+    let export = latest(&*connection, user)
+        .map_err(|_| "Failed to look up new export")?
+        .ok_or("Failed to look up new export")?;
+
+    let (export_id, user_id) = (export.id, user.id);
+    thread::spawn(move || {
+        let outcome = build(user_id);
+        // If even this fails, the export stays pending until it times out,
+        // and the user can ask again.
+        let _ = finish(export_id, user_id, outcome);
+    });
+    Ok(Some(export))
+}
+
+/// The user's most recently requested export, if any
+pub fn latest(conn: &Connection, user: &User) -> QueryResult<Option<DataExport>> {
+    data_exports::table
+        .filter(data_exports::user_id.eq(user.id))
+        .order(data_exports::id.desc())
+        .first::<DataExport>(conn)
+        .optional()
+}
+
+/// The user's most recently finished export which can be downloaded, if any
+///
+/// While a new export is being built, the previous one stays available.
+pub fn latest_ready(conn: &Connection, user: &User) -> QueryResult<Option<DataExport>> {
+    data_exports::table
+        .filter(data_exports::user_id.eq(user.id))
+        .filter(data_exports::status.eq(STATUS_READY))
+        .order(data_exports::id.desc())
+        .first::<DataExport>(conn)
+        .optional()
+}
+
+/// When a ready export stops being available for download
+pub fn expires_at(export: &DataExport) -> Option<NaiveDateTime> {
+    if export.status == STATUS_READY {
+        export.completed_at.map(|completed| completed + *EXPORT_LIFETIME)
+    } else {
+        None
+    }
+}
+
+/// Open a ready export's archive, unless it has expired
+pub fn open(export: &DataExport) -> io::Result<Option<File>> {
+    let file_name = match export.file_name {
+        Some(ref file_name) => file_name,
+        None => return Ok(None),
+    };
+    match expires_at(export) {
+        Some(expiry) if expiry > Utc::now().naive_utc() => {}
+        _ => return Ok(None),
+    }
+    File::open(PathBuf::from(&*EXPORT_DIR).join(file_name)).map(Some)
+}
+
+/// Remove every one of the user's exports
+///
+/// Exports with an id of `keep` or above are left alone.
+pub fn remove_for(conn: &Connection, user_id: i32, keep: Option<i32>) -> QueryResult<()> {
+    let keep = keep.unwrap_or(i32::max_value());
+    let doomed = data_exports::table
+        .filter(data_exports::user_id.eq(user_id))
+        .filter(data_exports::id.lt(keep))
+        .load::<DataExport>(conn)?;
+    diesel::delete(
+        data_exports::table
+            .filter(data_exports::user_id.eq(user_id))
+            .filter(data_exports::id.lt(keep)),
+    ).execute(conn)?;
+    for file_name in doomed.iter().filter_map(|export| export.file_name.as_ref()) {
+        // A leftover archive wastes space, but nothing will ever hand it out
+        let _ = fs::remove_file(PathBuf::from(&*EXPORT_DIR).join(file_name));
+    }
+    Ok(())
+}
+
+/// Build a user's archive and write it to `EXPORT_DIR`
+///
+/// Returns the archive's file name and size.
+fn build(user_id: i32) -> Result<(String, i64), String> {
+    let connection = CONNECTION_POOL.get().map_err(|e| e.to_string())?;
+    let user = users::table
+        .find(user_id)
+        .first::<User>(&*connection)
+        .map_err(|e| e.to_string())?;
+
+    let file_name = format!("{}-{}.zip", user.id, random_string(FILE_NONCE_LENGTH)?);
+    let path = PathBuf::from(&*EXPORT_DIR).join(&file_name);
+    fs::create_dir_all(&*EXPORT_DIR).map_err(|e| e.to_string())?;
+    let written = File::create(&path)
+        .map_err(|e| e.to_string())
+        .and_then(|file| write_archive(&*connection, &user, BufWriter::new(file)))
+        .and_then(|writer| writer.into_inner().map_err(|e| e.to_string()))
+        .and_then(|file| file.metadata().map_err(|e| e.to_string()));
+    match written {
+        Ok(metadata) => Ok((file_name, metadata.len() as i64)),
+        Err(e) => {
+            // Nothing will ever refer to a half-written archive
+            let _ = fs::remove_file(&path);
+            Err(e)
+        }
+    }
+}
+
+/// Record how building an export went, and clear away the ones it replaces
+fn finish(export_id: i32, user_id: i32, outcome: Result<(String, i64), String>) -> Result<(), &'static str> {
+    let connection = CONNECTION_POOL.get().map_err(
+        |_| "Couldn't get connection from pool",
+    )?;
+    let now = Utc::now().naive_utc();
+    let export = data_exports::table.find(export_id);
+
+    match outcome {
+        Ok((file_name, size)) => {
+            diesel::update(export)
+                .set((
+                    data_exports::status.eq(STATUS_READY),
+                    data_exports::completed_at.eq(now),
+                    data_exports::file_name.eq(file_name),
+                    data_exports::size.eq(size),
+                ))
+                .execute(&*connection)
+                .map_err(|_| "Failed to record finished export")?;
+            remove_for(&*connection, user_id, Some(export_id)).map_err(
+                |_| "Failed to remove old exports",
+            )?;
+        }
+        Err(_) => {
+            diesel::update(export)
+                .set((
+                    data_exports::status.eq(STATUS_FAILED),
+                    data_exports::completed_at.eq(now),
+                ))
+                .execute(&*connection)
+                .map_err(|_| "Failed to record failed export")?;
+        }
+    }
+    Ok(())
+}
+
+/// Look up the usernames of the users in some `(user id, timestamp)` pairs
+///
+/// Users who've since been purged are left out.
+fn with_usernames(
+    conn: &Connection,
+    related: Vec<(i32, NaiveDateTime)>,
+) -> QueryResult<Vec<(String, NaiveDateTime)>> {
+    let ids: Vec<i32> = related.iter().map(|&(id, _)| id).collect();
+    let names: HashMap<i32, String> = users::table
+        .filter(users::id.eq_any(ids))
+        .select((users::id, users::username))
+        .load::<(i32, String)>(conn)?
+        .into_iter()
+        .collect();
+    Ok(
+        related
+            .into_iter()
+            .filter_map(|(id, since)| names.get(&id).map(|name| (name.clone(), since)))
+            .collect(),
+    )
+}
+
+/// Call `write` with each batch of a user's pings in turn, newest first
+fn for_each_ping_batch<F>(conn: &Connection, user: &User, mut write: F) -> Result<(), String>
+where
+    F: FnMut(&[Ping]) -> Result<(), String>,
+{
+    let mut position = Position::Newest;
+    loop {
+        let batch = user.pings_page(conn, position, PING_BATCH_SIZE)
+            .map_err(|e| e.to_string())?;
+        write(&batch)?;
+        match batch.last() {
+            Some(last) if batch.len() == PING_BATCH_SIZE => {
+                position = Position::Before(last.cursor())
+            }
+            _ => return Ok(()),
+        }
+    }
+}
+
+/// Gather up everything about a user into a zip archive, written to `writer`
+///
+/// Returns the writer once the archive is complete.
+fn write_archive<W: Write + Seek>(conn: &Connection, user: &User, writer: W) -> Result<W, String> {
+    let fields = user.profile_fields(conn).map_err(|e| e.to_string())?;
+    let ping_count = user.ping_count(conn).map_err(|e| e.to_string())?;
+    let following = follows::table
+        .filter(follows::follower_id.eq(user.id))
+        .select((follows::followed_id, follows::timestamp))
+        .load(conn)
+        .and_then(|related| with_usernames(conn, related))
+        .map_err(|e| e.to_string())?;
+    let followers = follows::table
+        .filter(follows::followed_id.eq(user.id))
+        .select((follows::follower_id, follows::timestamp))
+        .load(conn)
+        .and_then(|related| with_usernames(conn, related))
+        .map_err(|e| e.to_string())?;
+    let blocked = blocks::table
+        .filter(blocks::blocker_id.eq(user.id))
+        .select((blocks::blocked_id, blocks::timestamp))
+        .load(conn)
+        .and_then(|related| with_usernames(conn, related))
+        .map_err(|e| e.to_string())?;
+
+    let mut zip = ZipWriter::new(writer);
+    // Images are compressed already
+    let stored = FileOptions::default().compression_method(CompressionMethod::Stored);
+    let deflated = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    // Fetch each image only as it's written, so that at most one is in memory
+    let mut media = Vec::new();
+    for &(kind, key) in [
+        (ImageKind::Avatar, &user.avatar_key),
+        (ImageKind::Banner, &user.banner_key),
+    ].iter()
+    {
+        if let Some(ref key) = *key {
+            let extension = key.rsplit('.').next().unwrap_or("");
+            for &(label, _, _) in kind.sizes() {
+                let data = BLOB_STORE.get(&key.replace(SIZE_PLACEHOLDER, label))?;
+                let name = format!("media/{}-{}.{}", kind.name(), label, extension);
+                zip.start_file(name.as_str(), stored).map_err(|e| e.to_string())?;
+                zip.write_all(&data).map_err(|e| e.to_string())?;
+                media.push(name);
+            }
+        }
+    }
+
+    let profile = json!({
+        "username": user.username,
+        "real_name": user.real_name,
+        "blurb": user.blurb,
+        "location": user.location,
+        "website": user.website,
+        "pronouns": user.pronouns,
+        "fields": fields.iter().map(|field| json!({
+            "label": field.label,
+            "value": field.value,
+        })).collect::<Vec<_>>(),
+        "email": user.email,
+        "email_verified_at": user.email_verified_at,
+        "two_factor_enabled": user.two_factor_enabled(),
+        "joined": user.joined_at,
+        "exported": Utc::now().naive_utc(),
+    });
+    let related_json = |related: &[(String, NaiveDateTime)]| {
+        json!(
+            related
+                .iter()
+                .map(|&(ref username, since)| json!({"username": username, "since": since}))
+                .collect::<Vec<_>>()
+        )
+    };
+
+    let documents = [
+        ("profile.json", profile),
+        ("following.json", related_json(&following)),
+        ("followers.json", related_json(&followers)),
+        ("blocks.json", related_json(&blocked)),
+    ];
+    for &(name, ref document) in documents.iter() {
+        zip.start_file(name, deflated).map_err(|e| e.to_string())?;
+        serde_json::to_writer_pretty(&mut zip, document).map_err(|e| e.to_string())?;
+    }
+
+    // Pings are written as they're fetched, so the array is put together by hand
+    zip.start_file("pings.json", deflated).map_err(|e| e.to_string())?;
+    zip.write_all(b"[").map_err(|e| e.to_string())?;
+    let mut separator: &[u8] = b"\n";
+    for_each_ping_batch(conn, user, |batch| {
+        for ping in batch {
+            zip.write_all(separator).map_err(|e| e.to_string())?;
+            serde_json::to_writer_pretty(
+                &mut zip,
+                &json!({
+                    "id": ping.id,
+                    "timestamp": ping.timestamp,
+                    "content": ping.content,
+                    "likes": ping.likes,
+                    "echoes": ping.echoes,
+                }),
+            ).map_err(|e| e.to_string())?;
+            separator = b",\n";
+        }
+        Ok(())
+    })?;
+    zip.write_all(b"\n]").map_err(|e| e.to_string())?;
+
+    zip.start_file("index.html", deflated).map_err(|e| e.to_string())?;
+    zip.write_all(render_index_head(user, ping_count).as_bytes())
+        .map_err(|e| e.to_string())?;
+    for_each_ping_batch(conn, user, |batch| {
+        let items: String = batch.iter().map(render_index_ping).collect();
+        zip.write_all(items.as_bytes()).map_err(|e| e.to_string())
+    })?;
+    zip.write_all(render_index_tail(&following, &followers, &blocked, &media).as_bytes())
+        .map_err(|e| e.to_string())?;
+
+    zip.finish().map_err(|e| e.to_string())
+}
+
+/// Escape text for inclusion in HTML
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Render the start of the archive's human-readable index, up to its list of pings
+///
+/// The pings follow, each rendered by `render_index_ping`, then the rest of
+/// the index from `render_index_tail`.
+fn render_index_head(user: &User, ping_count: i64) -> String {
+    format!(
+        "<!DOCTYPE html>\n\
+         <html>\n<head>\n<meta charset=\"utf-8\">\n<title>sonar data for @{username}</title>\n</head>\n\
+         <body>\n<h1>sonar data for @{username}</h1>\n\
+         <p>The same data is in the JSON files alongside this one.</p>\n\
+         <h2>Profile</h2>\n<dl>\n\
+         <dt>Real name</dt><dd>{real_name}</dd>\n\
+         <dt>Blurb</dt><dd>{blurb}</dd>\n\
+         <dt>Location</dt><dd>{location}</dd>\n\
+         <dt>Website</dt><dd>{website}</dd>\n\
+         <dt>Pronouns</dt><dd>{pronouns}</dd>\n\
+         <dt>Email</dt><dd>{email}</dd>\n\
+         </dl>\n\
+         <h2>Pings ({ping_count})</h2>\n<ul>\n",
+        username = escape_html(&user.username),
+        real_name = escape_html(&user.real_name),
+        blurb = escape_html(&user.blurb),
+        location = escape_html(&user.location),
+        website = escape_html(&user.website),
+        pronouns = escape_html(&user.pronouns),
+        email = escape_html(user.email.as_ref().map(|e| e.as_str()).unwrap_or("")),
+        ping_count = ping_count,
+    )
+}
+
+/// Render one ping in the archive's human-readable index
+fn render_index_ping(ping: &Ping) -> String {
+    format!(
+        "<li><time>{}</time> {} ({} likes, {} echoes)</li>\n",
+        ping.timestamp,
+        escape_html(&ping.content),
+        ping.likes,
+        ping.echoes
+    )
+}
+
+/// Render the rest of the archive's human-readable index, after its list of pings
+fn render_index_tail(
+    following: &[(String, NaiveDateTime)],
+    followers: &[(String, NaiveDateTime)],
+    blocked: &[(String, NaiveDateTime)],
+    media: &[String],
+) -> String {
+    let related_list = |related: &[(String, NaiveDateTime)]| -> String {
+        if related.is_empty() {
+            return String::from("<p>None.</p>\n");
+        }
+        let items: String = related
+            .iter()
+            .map(|&(ref username, since)| {
+                format!("<li>@{} (since {})</li>\n", escape_html(username), since)
+            })
+            .collect();
+        format!("<ul>\n{}</ul>\n", items)
+    };
+    let media_items: String = media
+        .iter()
+        .map(|name| {
+            format!("<li><a href=\"{0}\">{0}</a></li>\n", escape_html(name))
+        })
+        .collect();
+
+    format!(
+        "</ul>\n\
+         <h2>Following</h2>\n{following}\
+         <h2>Followers</h2>\n{followers}\
+         <h2>Blocked</h2>\n{blocked}\
+         <h2>Images</h2>\n<ul>\n{media}</ul>\n\
+         </body>\n</html>\n",
+        following = related_list(following),
+        followers = related_list(followers),
+        blocked = related_list(blocked),
+        media = media_items,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_html() {
+        assert_eq!(
+            escape_html("<script>alert(\"hi\" & 'bye')</script>"),
+            "&lt;script&gt;alert(&quot;hi&quot; &amp; &#39;bye&#39;)&lt;/script&gt;"
+        );
+        assert_eq!(escape_html("plain text"), "plain text");
+    }
+}
@@ -0,0 +1,250 @@
+//! Finding and checking links in user-supplied text.
+//!
+//! Clients shouldn't have to guess which parts of a user's text are links,
+//! nor trust that what looks like one is safe to open. Instead, text which
+//! may contain links is returned alongside the links found in it, each
+//! normalized and limited to `http` and `https`.
+
+use unicode_segmentation::UnicodeSegmentation;
+use url::Url;
+
+/// Longest permissible `display_url`, in characters, before it's shortened
+const DISPLAY_URL_MAX_LENGTH: usize = 30;
+
+/// Characters which may follow a link in prose without being part of it
+const TRAILING_PUNCTUATION: &'static [char] = &['.', ',', ';', ':', '!', '?', '\'', '"', ')', ']', '>'];
+
+/// A reason a URL was rejected
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Problem {
+    /// Couldn't be parsed as a URL at all
+    Malformed,
+    /// Uses some scheme other than `http` or `https`
+    UnsupportedScheme,
+    /// Carries a username or password
+    HasCredentials,
+}
+
+impl Problem {
+    /// A stable, machine-readable code which clients can use to explain the problem
+    pub fn code(&self) -> &'static str {
+        match *self {
+            Problem::Malformed => "malformed",
+            Problem::UnsupportedScheme => "unsupported_scheme",
+            Problem::HasCredentials => "has_credentials",
+        }
+    }
+
+    /// A human-readable explanation of the problem
+    pub fn message(&self) -> String {
+        String::from(match *self {
+            Problem::Malformed => "URL is not valid",
+            Problem::UnsupportedScheme => "URL must begin with http:// or https://",
+            Problem::HasCredentials => "URL must not contain a username or password",
+        })
+    }
+}
+
+/// A link found in some text
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Link {
+    /// The link's target, normalized
+    pub url: String,
+    /// A short form of the link, for showing in place of the original text
+    pub display_url: String,
+    /// Where the link starts and ends in the text, in grapheme clusters
+    ///
+    /// That's the unit in which a ping's length is counted (see
+    /// `ping::length`), so clients can slice the text the same way for both.
+    /// The end is exclusive.
+    pub indices: [usize; 2],
+}
+
+/// Parse and normalize a URL, accepting only `http` and `https`
+///
+/// A URL without a scheme is assumed to be `https`, since people rarely
+/// type one when they fill in their website.
+pub fn normalize(url: &str) -> Result<String, Problem> {
+    let url = url.trim();
+    let parsed = if url.contains("://") {
+        Url::parse(url)
+    } else {
+        Url::parse(&format!("https://{}", url))
+    }.map_err(|_| Problem::Malformed)?;
+
+    if parsed.scheme() != "http" && parsed.scheme() != "https" {
+        return Err(Problem::UnsupportedScheme);
+    }
+    if !parsed.username().is_empty() || parsed.password().is_some() {
+        return Err(Problem::HasCredentials);
+    }
+    match parsed.host_str() {
+        Some(host) if host.contains('.') || host == "localhost" => {}
+        _ => return Err(Problem::Malformed),
+    }
+    Ok(parsed.into_string())
+}
+
+/// A short, human-friendly form of a normalized URL
+///
+/// This drops the scheme, a leading `www.` and a bare trailing `/`, and
+/// shortens whatever's left to `DISPLAY_URL_MAX_LENGTH`.
+fn display_url(url: &str) -> String {
+    let without_scheme = url.splitn(2, "://").nth(1).unwrap_or(url);
+    let without_www = if without_scheme.starts_with("www.") {
+        &without_scheme[4..]
+    } else {
+        without_scheme
+    };
+    let trimmed = without_www.trim_right_matches('/');
+    if trimmed.chars().count() > DISPLAY_URL_MAX_LENGTH {
+        let mut shortened: String = trimmed.chars().take(DISPLAY_URL_MAX_LENGTH - 1).collect();
+        shortened.push('…');
+        shortened
+    } else {
+        trimmed.to_string()
+    }
+}
+
+/// Find the `http` and `https` links in some text
+///
+/// Only links written out with their scheme are found: `example.com` alone
+/// is left as text. Punctuation which ends a sentence isn't taken as part of
+/// the link, and neither is a closing bracket without an opening one.
+pub fn find(text: &str) -> Vec<Link> {
+    let mut links = Vec::new();
+    // The byte offset of each word; every separator is a single character
+    let mut offset = 0;
+    for word in text.split(|c: char| c.is_whitespace()) {
+        if let Some((url, start, end)) = find_in_word(word) {
+            links.push(Link {
+                display_url: display_url(&url),
+                url: url,
+                indices: [
+                    grapheme_index(text, offset + start),
+                    grapheme_index(text, offset + end),
+                ],
+            });
+        }
+        let separator = text[offset + word.len()..].chars().next();
+        offset += word.len() + separator.map_or(0, char::len_utf8);
+    }
+    links
+}
+
+/// How many grapheme clusters of `text` start before a byte offset into it
+///
+/// Clusters can span whitespace, as when a combining mark follows a space,
+/// so this counts across the whole text rather than word by word.
+fn grapheme_index(text: &str, byte: usize) -> usize {
+    text.grapheme_indices(true)
+        .take_while(|&(start, _)| start < byte)
+        .count()
+}
+
+/// Find a link in a single word
+///
+/// Returns the normalized link, and the byte offsets in `word` at which it
+/// starts and ends.
+fn find_in_word(word: &str) -> Option<(String, usize, usize)> {
+    // ASCII lowercasing keeps byte offsets the same
+    let lowercase = word.to_ascii_lowercase();
+    let start = match (lowercase.find("http://"), lowercase.find("https://")) {
+        (Some(a), Some(b)) => a.min(b),
+        (Some(a), None) | (None, Some(a)) => a,
+        (None, None) => return None,
+    };
+    // `xhttp://` isn't a link
+    if word[..start].chars().last().map_or(false, |c| c.is_alphanumeric()) {
+        return None;
+    }
+
+    let mut candidate = &word[start..];
+    loop {
+        let trimmed = candidate.trim_right_matches(TRAILING_PUNCTUATION);
+        // Put back a closing parenthesis that closes one in the link itself,
+        // as in wiki links
+        let trimmed = if trimmed.len() < candidate.len() && candidate[trimmed.len()..].starts_with(')') &&
+            trimmed.matches('(').count() > trimmed.matches(')').count()
+        {
+            &candidate[..trimmed.len() + 1]
+        } else {
+            trimmed
+        };
+        if trimmed.len() == candidate.len() {
+            break;
+        }
+        candidate = trimmed;
+    }
+
+    let url = normalize(candidate).ok()?;
+    Some((url, start, start + candidate.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("https://example.com"), Ok(String::from("https://example.com/")));
+        assert_eq!(normalize("example.com/about"), Ok(String::from("https://example.com/about")));
+        assert_eq!(normalize("HTTP://Example.COM"), Ok(String::from("http://example.com/")));
+    }
+
+    #[test]
+    fn test_normalize_rejects() {
+        assert_eq!(normalize("javascript:alert(1)"), Err(Problem::Malformed));
+        assert_eq!(normalize("javascript://alert(1)"), Err(Problem::UnsupportedScheme));
+        assert_eq!(normalize("ftp://example.com"), Err(Problem::UnsupportedScheme));
+        assert_eq!(normalize("https://user:pw@example.com"), Err(Problem::HasCredentials));
+        assert_eq!(normalize("not a url"), Err(Problem::Malformed));
+        assert_eq!(normalize(""), Err(Problem::Malformed));
+    }
+
+    #[test]
+    fn test_display_url() {
+        assert_eq!(display_url("https://www.example.com/"), "example.com");
+        assert_eq!(
+            display_url("https://example.com/a/very/long/path/indeed/yes"),
+            "example.com/a/very/long/path/…"
+        );
+    }
+
+    #[test]
+    fn test_find() {
+        let links = find("Read https://example.com/post, then (http://example.org).");
+        assert_eq!(links.len(), 2);
+        assert_eq!(links[0].url, "https://example.com/post");
+        assert_eq!(links[0].indices, [5, 29]);
+        assert_eq!(links[1].url, "http://example.org/");
+        assert_eq!(links[1].indices, [37, 55]);
+    }
+
+    #[test]
+    fn test_find_counts_characters() {
+        let links = find("café → https://example.com");
+        assert_eq!(links[0].indices, [7, 26]);
+    }
+
+    #[test]
+    fn test_find_counts_grapheme_clusters() {
+        // An `e` followed by a combining acute accent is one cluster, but two code points
+        let links = find("cafe\u{301} https://example.com");
+        assert_eq!(links[0].indices, [5, 24]);
+        // A combining mark after a space joins the space's cluster
+        let links = find("a \u{301}https://example.com");
+        assert_eq!(links[0].indices, [2, 21]);
+    }
+
+    #[test]
+    fn test_find_keeps_balanced_parentheses() {
+        let links = find("https://en.wikipedia.org/wiki/Sonar_(disambiguation)");
+        assert_eq!(links[0].url, "https://en.wikipedia.org/wiki/Sonar_(disambiguation)");
+    }
+
+    #[test]
+    fn test_find_ignores_other_schemes() {
+        assert!(find("javascript:alert(1) ftp://example.com xhttp://example.com").is_empty());
+    }
+}
@@ -0,0 +1,154 @@
+//! Outgoing email.
+//!
+//! Everything which sends mail does so through the `Mailer` trait, so that
+//! development and test environments don't need a working SMTP server.
+//! The mailer in use is chosen by the `MAILER` setting; see `config`.
+
+use chrono::Utc;
+use config::{MAILER_KIND, MAIL_DIR, MAIL_FROM, SMTP_HOST, SMTP_PASSWORD, SMTP_USERNAME};
+use lettre::{EmailAddress, Envelope, SendableEmail, SmtpClient, Transport};
+use lettre::smtp::authentication::Credentials;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::PathBuf;
+
+lazy_static! {
+    /// The mailer configured by the `MAILER` setting
+    pub static ref MAILER: Box<Mailer + Send + Sync> = match MAILER_KIND.as_str() {
+        "smtp" => Box::new(SmtpMailer),
+        "file" => Box::new(FileMailer { dir: PathBuf::from(&*MAIL_DIR) }),
+        "stdout" => Box::new(StdoutMailer),
+        other => panic!("Unknown MAILER: {}", other),
+    };
+}
+
+/// The longest email address anyone can actually deliver mail to
+const MAX_ADDRESS_LENGTH: usize = 254;
+
+/// Check and normalize an email address.
+///
+/// We're deliberately lenient about what we accept, since the only real test
+/// of an address is whether mail to it arrives. Surrounding whitespace is
+/// trimmed, and the whole address lowercased: strictly, the part before the `@`
+/// may be case-sensitive, but no real mail server treats it that way, and we
+/// don't want `Me@example.com` and `me@example.com` to be different accounts.
+///
+/// Returns `None` if the address is obviously unusable.
+pub fn normalize_address(address: &str) -> Option<String> {
+    let address = address.trim().to_lowercase();
+    if address.len() > MAX_ADDRESS_LENGTH ||
+        address.chars().any(|c| c.is_whitespace() || c.is_control())
+    {
+        return None;
+    }
+
+    let split_index = address.rfind('@')?;
+    let (local, domain) = address.split_at(split_index);
+    let domain = &domain[1..];
+    if local.is_empty() || domain.is_empty() || !domain.contains('.') || domain.starts_with('.') ||
+        domain.ends_with('.')
+    {
+        return None;
+    }
+
+    Some(address)
+}
+
+/// Something which can send a plain-text email
+pub trait Mailer {
+    /// Send a message with the given subject and body to a single recipient
+    fn send(&self, to: &str, subject: &str, body: &str) -> Result<(), String>;
+}
+
+/// Render a complete message, headers and all
+///
+/// Line breaks are stripped from header values, so that nobody can sneak
+/// extra headers in through an email address.
+fn render(to: &str, subject: &str, body: &str) -> String {
+    let header = |value: &str| value.replace(|c: char| c == '\r' || c == '\n', "");
+    format!(
+        "From: {}\r\nTo: {}\r\nSubject: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n{}\r\n",
+        header(&*MAIL_FROM),
+        header(to),
+        header(subject),
+        body
+    )
+}
+
+/// Sends mail through the SMTP server given by the `SMTP_*` settings
+pub struct SmtpMailer;
+
+impl Mailer for SmtpMailer {
+    fn send(&self, to: &str, subject: &str, body: &str) -> Result<(), String> {
+        let envelope = Envelope::new(
+            Some(EmailAddress::new(MAIL_FROM.clone()).map_err(|e| e.to_string())?),
+            vec![EmailAddress::new(to.to_string()).map_err(|e| e.to_string())?],
+        ).map_err(|e| e.to_string())?;
+        let message_id = format!("{}@sonar", Utc::now().timestamp_nanos());
+        let email = SendableEmail::new(
+            envelope,
+            message_id,
+            render(to, subject, body).into_bytes(),
+        );
+
+        let mut client = SmtpClient::new_simple(&SMTP_HOST).map_err(|e| e.to_string())?;
+        if !SMTP_USERNAME.is_empty() {
+            client = client.credentials(Credentials::new(
+                SMTP_USERNAME.clone(),
+                SMTP_PASSWORD.clone(),
+            ));
+        }
+        client.transport().send(email).map(|_| ()).map_err(
+            |e| e.to_string(),
+        )
+    }
+}
+
+/// Writes each message to its own file in a directory, for development and tests
+pub struct FileMailer {
+    pub dir: PathBuf,
+}
+
+impl Mailer for FileMailer {
+    fn send(&self, to: &str, subject: &str, body: &str) -> Result<(), String> {
+        fs::create_dir_all(&self.dir).map_err(|e| e.to_string())?;
+        let recipient = to.replace(|c: char| !(c.is_alphanumeric() || c == '@' || c == '.'), "_");
+        let path = self.dir.join(format!(
+            "{}-{}.eml",
+            Utc::now().timestamp_nanos(),
+            recipient
+        ));
+        let mut file = File::create(path).map_err(|e| e.to_string())?;
+        file.write_all(render(to, subject, body).as_bytes())
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Prints each message to stdout, for development
+pub struct StdoutMailer;
+
+impl Mailer for StdoutMailer {
+    fn send(&self, to: &str, subject: &str, body: &str) -> Result<(), String> {
+        println!("{}", render(to, subject, body));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_address() {
+        assert_eq!(
+            normalize_address("  Someone@Example.COM "),
+            Some(String::from("someone@example.com"))
+        );
+        assert_eq!(normalize_address("someone"), None);
+        assert_eq!(normalize_address("@example.com"), None);
+        assert_eq!(normalize_address("someone@localhost"), None);
+        assert_eq!(normalize_address("someone@example.com."), None);
+        assert_eq!(normalize_address("some one@example.com"), None);
+        assert_eq!(normalize_address("someone@example.com\r\nBcc: x@y.z"), None);
+    }
+}
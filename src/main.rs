@@ -3,16 +3,28 @@
 #![feature(plugin)]
 #![feature(try_trait)]
 #![plugin(rocket_codegen)]
+extern crate argon2;
 extern crate argon2rs;
+extern crate base32;
+extern crate base64;
 extern crate chrono;
 #[macro_use]
 extern crate diesel;
 #[macro_use]
 extern crate diesel_codegen;
 extern crate dotenv;
+extern crate image;
 #[macro_use]
 extern crate lazy_static;
+extern crate lettre;
+#[macro_use]
+extern crate log;
+extern crate multipart;
+#[cfg(test)]
+#[macro_use]
+extern crate proptest;
 extern crate rand;
+extern crate ring;
 extern crate rocket;
 #[macro_use]
 extern crate rocket_contrib;
@@ -20,21 +32,77 @@ extern crate r2d2;
 extern crate r2d2_diesel;
 #[macro_use]
 extern crate serde_derive;
+extern crate serde_json;
+extern crate unicode_segmentation;
+extern crate url;
+extern crate zip;
 
 
 pub mod auth;
+pub mod blob;
+pub mod config;
 pub mod db;
+mod export;
+pub mod links;
+pub mod mail;
+pub mod media;
+pub mod page;
+pub mod ping;
+mod purge;
 mod models;
 #[macro_use]
 pub mod status;
 mod schema;
+pub mod username;
 mod views;
 
 use views::*;
 
 fn main() {
+    auth::pw::Params::current().check().expect(
+        "argon2 rejects the configured ARGON2_* parameters",
+    );
+    purge::spawn();
     rocket::ignite()
-        .mount("/v1", routes![create_user])
-        .catch(errors![not_found])
+        .mount(
+            "/v1",
+            routes![
+                create_user,
+                get_user,
+                update_user,
+                delete_user,
+                change_username,
+                change_password,
+                resend_email_verification,
+                verify_email,
+                request_password_reset,
+                confirm_password_reset,
+                create_session,
+                list_sessions,
+                refresh_session,
+                delete_session,
+                begin_totp_enrollment,
+                confirm_totp_enrollment,
+                disable_totp,
+                answer_login_challenge,
+                put_avatar,
+                delete_avatar,
+                put_banner,
+                delete_banner,
+                request_export,
+                get_export,
+                download_export,
+                create_ping,
+                get_ping,
+                delete_ping,
+                get_user_pings,
+                follow_user,
+                unfollow_user,
+                get_followers,
+                get_following,
+            ],
+        )
+        .mount(&*config::BLOB_BASE_URL, routes![serve_media])
+        .catch(errors![unauthorized, not_found])
         .launch();
 }
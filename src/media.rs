@@ -0,0 +1,267 @@
+//! Processing of uploaded profile images.
+//!
+//! Uploads are decoded and re-encoded from their pixels alone, which drops
+//! EXIF data, embedded thumbnails, and any other metadata the original
+//! carried. Each upload is stored in several sizes.
+//!
+//! A small file can still claim enormous dimensions, and decoding allocates
+//! for every pixel claimed; so the dimensions are read from the header, and
+//! checked against `IMAGE_MAX_PIXELS`, before anything is decoded.
+
+use std::io::Cursor;
+
+use image::{self, DynamicImage, FilterType, GenericImage, ImageDecoder, ImageFormat,
+            ImageResult};
+use image::gif;
+use image::jpeg::{JPEGDecoder, JPEGEncoder};
+use image::png::PNGDecoder;
+
+use blob::BLOB_STORE;
+use config::IMAGE_MAX_PIXELS;
+
+/// The placeholder in a stored image's key template which each size's label replaces
+pub const SIZE_PLACEHOLDER: &'static str = "{size}";
+
+/// Quality with which JPEG renditions are encoded
+const JPEG_QUALITY: u8 = 85;
+
+/// What an uploaded image is for
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageKind {
+    Avatar,
+    Banner,
+}
+
+impl ImageKind {
+    /// The name of this kind of image, as used in URLs and storage keys
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ImageKind::Avatar => "avatar",
+            ImageKind::Banner => "banner",
+        }
+    }
+
+    /// The sizes each image of this kind is stored in: a label, a width and a height
+    ///
+    /// Images are scaled and cropped to fill each size exactly.
+    pub fn sizes(&self) -> &'static [(&'static str, u32, u32)] {
+        match *self {
+            ImageKind::Avatar => &[("400", 400, 400), ("200", 200, 200), ("48", 48, 48)],
+            ImageKind::Banner => &[("1500x500", 1500, 500), ("600x200", 600, 200)],
+        }
+    }
+}
+
+/// A reason an upload was rejected
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rejection {
+    /// Not a PNG, JPEG or GIF, or not the type it claimed to be
+    UnsupportedType,
+    /// Claimed to be a supported type, but couldn't be decoded
+    Corrupt,
+    /// Has more pixels than `IMAGE_MAX_PIXELS`
+    TooLarge,
+}
+
+/// One size of a processed image
+pub struct Rendition {
+    pub label: &'static str,
+    pub data: Vec<u8>,
+}
+
+/// The file extension processed images of the given source format get
+///
+/// Photos stay JPEGs; everything else becomes a PNG, so that transparency survives.
+pub fn extension(format: ImageFormat) -> &'static str {
+    match format {
+        ImageFormat::JPEG => "jpg",
+        _ => "png",
+    }
+}
+
+/// Work out what format an upload is in, checking it against the type it claimed to be
+///
+/// Only PNG, JPEG and GIF are accepted. An animated GIF keeps only its first frame.
+pub fn sniff(data: &[u8], claimed_type: Option<&str>) -> Result<ImageFormat, Rejection> {
+    let format = match image::guess_format(data) {
+        Ok(format @ ImageFormat::PNG) |
+        Ok(format @ ImageFormat::JPEG) |
+        Ok(format @ ImageFormat::GIF) => format,
+        _ => return Err(Rejection::UnsupportedType),
+    };
+    let expected = match format {
+        ImageFormat::PNG => "image/png",
+        ImageFormat::JPEG => "image/jpeg",
+        _ => "image/gif",
+    };
+    match claimed_type {
+        Some(claimed) if claimed.split(';').next().unwrap_or("").trim() != expected => {
+            Err(Rejection::UnsupportedType)
+        }
+        _ => Ok(format),
+    }
+}
+
+/// Read an upload's dimensions from its header, without decoding any pixels
+fn dimensions(data: &[u8], format: ImageFormat) -> ImageResult<(u32, u32)> {
+    let data = Cursor::new(data);
+    match format {
+        ImageFormat::PNG => PNGDecoder::new(data).dimensions(),
+        ImageFormat::JPEG => JPEGDecoder::new(data).dimensions(),
+        _ => gif::Decoder::new(data).dimensions(),
+    }
+}
+
+/// Check, before decoding it, that an upload has no more than `max_pixels` pixels
+fn check_dimensions(data: &[u8], format: ImageFormat, max_pixels: u64) -> Result<(), Rejection> {
+    let (width, height) = dimensions(data, format).map_err(|_| Rejection::Corrupt)?;
+    if width as u64 * height as u64 > max_pixels {
+        return Err(Rejection::TooLarge);
+    }
+    Ok(())
+}
+
+/// Decode an upload and render it in every size its kind needs
+pub fn process(
+    kind: ImageKind,
+    data: &[u8],
+    format: ImageFormat,
+) -> Result<Vec<Rendition>, Rejection> {
+    check_dimensions(data, format, *IMAGE_MAX_PIXELS)?;
+    let source = image::load_from_memory_with_format(data, format).map_err(
+        |_| Rejection::Corrupt,
+    )?;
+
+    kind.sizes()
+        .iter()
+        .map(|&(label, width, height)| {
+            let data = encode(&fill(&source, width, height), format).map_err(
+                |_| Rejection::Corrupt,
+            )?;
+            Ok(Rendition {
+                label: label,
+                data: data,
+            })
+        })
+        .collect()
+}
+
+/// Encode a rendition: as a JPEG if the upload was one, and a PNG otherwise
+fn encode(image: &DynamicImage, format: ImageFormat) -> ImageResult<Vec<u8>> {
+    let mut data = Vec::new();
+    match format {
+        ImageFormat::JPEG => {
+            let (width, height) = image.dimensions();
+            JPEGEncoder::new_with_quality(&mut data, JPEG_QUALITY).encode(
+                &image.raw_pixels(),
+                width,
+                height,
+                image.color(),
+            )?
+        }
+        _ => image.save(&mut data, ImageFormat::PNG)?,
+    }
+    Ok(data)
+}
+
+/// Remove every size of a stored image from the blob store
+///
+/// This is best-effort: a file left behind wastes space, but nothing links to it.
+pub fn delete_stored(kind: ImageKind, key: &str) {
+    for &(label, _, _) in kind.sizes() {
+        let _ = BLOB_STORE.delete(&key.replace(SIZE_PLACEHOLDER, label));
+    }
+}
+
+/// Scale an image to cover the given size, then crop off whatever overhangs it
+fn fill(source: &DynamicImage, width: u32, height: u32) -> DynamicImage {
+    let (source_width, source_height) = source.dimensions();
+    // Compare aspect ratios without dividing: is the source wider than the target?
+    let (crop_width, crop_height) = if source_width as u64 * height as u64 >
+        source_height as u64 * width as u64
+    {
+        (
+            (source_height as u64 * width as u64 / height as u64) as u32,
+            source_height,
+        )
+    } else {
+        (
+            source_width,
+            (source_width as u64 * height as u64 / width as u64) as u32,
+        )
+    };
+    let x = (source_width - crop_width) / 2;
+    let y = (source_height - crop_height) / 2;
+    source
+        .clone()
+        .crop(x, y, crop_width.max(1), crop_height.max(1))
+        .resize_exact(width, height, FilterType::Lanczos3)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer, Rgb};
+
+    fn sample(width: u32, height: u32, format: ImageFormat) -> Vec<u8> {
+        let image = DynamicImage::ImageRgb8(ImageBuffer::from_pixel(width, height, Rgb([200, 100, 50])));
+        encode(&image, format).unwrap()
+    }
+
+    #[test]
+    fn test_sniff() {
+        let png = sample(10, 10, ImageFormat::PNG);
+        assert_eq!(sniff(&png, Some("image/png")), Ok(ImageFormat::PNG));
+        assert_eq!(sniff(&png, None), Ok(ImageFormat::PNG));
+        assert_eq!(sniff(&png, Some("image/jpeg")), Err(Rejection::UnsupportedType));
+        assert_eq!(
+            sniff(b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>", Some("image/svg+xml")),
+            Err(Rejection::UnsupportedType)
+        );
+    }
+
+    #[test]
+    fn test_process_sizes() {
+        let jpeg = sample(640, 480, ImageFormat::JPEG);
+        let renditions = process(ImageKind::Banner, &jpeg, ImageFormat::JPEG).unwrap();
+        assert_eq!(renditions.len(), ImageKind::Banner.sizes().len());
+        for (rendition, &(label, width, height)) in renditions.iter().zip(ImageKind::Banner.sizes()) {
+            assert_eq!(rendition.label, label);
+            let decoded = image::load_from_memory_with_format(&rendition.data, ImageFormat::JPEG)
+                .unwrap();
+            assert_eq!(decoded.dimensions(), (width, height));
+        }
+    }
+
+    #[test]
+    fn test_dimensions_checked_before_decoding() {
+        let png = sample(100, 50, ImageFormat::PNG);
+        assert_eq!(check_dimensions(&png, ImageFormat::PNG, 5000), Ok(()));
+        assert_eq!(
+            check_dimensions(&png, ImageFormat::PNG, 4999),
+            Err(Rejection::TooLarge)
+        );
+
+        // A 43-byte GIF claiming to be 65535 pixels square
+        let mut gif = b"GIF89a\x01\x00\x01\x00\x80\x00\x00\xff\xff\xff\x00\x00\x00!\xf9\x04\x01\x00\
+                        \x00\x00\x00,\x00\x00\x00\x00\x01\x00\x01\x00\x00\x02\x02D\x01\x00;"
+            .to_vec();
+        for byte in gif[6..10].iter_mut() {
+            *byte = 0xff;
+        }
+        assert_eq!(
+            check_dimensions(&gif, ImageFormat::GIF, 25_000_000),
+            Err(Rejection::TooLarge)
+        );
+    }
+
+    #[test]
+    fn test_corrupt() {
+        let mut png = sample(10, 10, ImageFormat::PNG);
+        png.truncate(40);
+        assert_eq!(
+            process(ImageKind::Avatar, &png, ImageFormat::PNG).err(),
+            Some(Rejection::Corrupt)
+        );
+    }
+}
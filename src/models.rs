@@ -1,11 +1,19 @@
 //! Models for sonar go here
 use auth::pw::SaltyPassword;
-use chrono::NaiveDateTime;
+use config::USERNAME_GRACE_PERIOD;
+use chrono::{NaiveDateTime, Utc};
 use db::Connection;
 use diesel;
+use diesel::Connection as ConnectionTrait;
 use diesel::prelude::*;
-use diesel::result::QueryResult;
-use schema::{users, pings, auth_tokens};
+use diesel::result::{DatabaseErrorKind, QueryResult};
+use diesel::result::Error::{DatabaseError, NotFound};
+use media::ImageKind;
+use page::{Cursor, Position};
+use username::{normalize as normalize_username, skeleton as username_skeleton};
+use schema::{users, pings, auth_tokens, password_reset_tokens, email_verification_tokens,
+             recovery_codes, login_challenges, login_throttles, failed_logins, follows, blocks,
+             former_usernames, profile_fields, data_exports};
 
 #[derive(Identifiable, Queryable)]
 pub struct User {
@@ -14,19 +22,426 @@ pub struct User {
     password: String,
     pub real_name: String,
     pub blurb: String,
+    /// Set when the stored password can't be used, so the user must reset it
+    pub password_reset_required: bool,
+    pub email: Option<String>,
+    /// When the user proved that `email` is theirs, if they have
+    pub email_verified_at: Option<NaiveDateTime>,
+    /// The user's TOTP secret, set as soon as they begin enrolling
+    pub totp_secret: Option<Vec<u8>>,
+    /// When the user finished enrolling in two-factor authentication, if they have
+    pub totp_enabled_at: Option<NaiveDateTime>,
+    /// The time step of the last TOTP code this user used
+    pub totp_last_step: Option<i32>,
+    /// See `username::normalize`
+    pub username_normalized: String,
+    /// See `username::skeleton`
+    pub username_skeleton: String,
+    /// When the user signed up; unknown for users from before we kept track
+    pub joined_at: Option<NaiveDateTime>,
+    /// Blob store key template for the user's avatar, if they have one
+    pub avatar_key: Option<String>,
+    /// Blob store key template for the user's banner, if they have one
+    pub banner_key: Option<String>,
+    pub location: String,
+    /// Normalized by `links::normalize`, or empty
+    pub website: String,
+    pub pronouns: String,
+    /// When the user asked for their account to be deleted, if they have
+    pub deactivated_at: Option<NaiveDateTime>,
 }
 
 impl User {
+    /// Whether this user needs a second factor to sign in
+    pub fn two_factor_enabled(&self) -> bool {
+        self.totp_enabled_at.is_some()
+    }
+
+    /// Whether this user goes by the given username, ignoring case
+    pub fn is_named(&self, username: &str) -> bool {
+        self.username_normalized == normalize_username(username)
+    }
+
+    /// Find the user with a given username, if there is one
+    ///
+    /// Usernames are matched case-insensitively.
+    pub fn find_by_username(conn: &Connection, username: &str) -> QueryResult<Option<User>> {
+        users::table
+            .filter(users::username_normalized.eq(normalize_username(username)))
+            .first::<User>(conn)
+            .optional()
+    }
+
     /// Validated a given username and plaintext password
     ///
     /// Return `true` if the given username exists and matches the given password
-    fn validate(conn: &Connection, username: &str, password: &str) -> bool {
-        unimplemented!()
+    pub fn validate(conn: &Connection, username: &str, password: &str) -> bool {
+        User::get_validated(conn, username, password).is_ok()
     }
 
     /// Get the User object corresponding to a given username and plaintext password
-    fn get_validated(conn: &Connection, username: &str, password: &str) -> QueryResult<User> {
-        unimplemented!()
+    ///
+    /// If the user doesn't exist, or the password doesn't match, this returns
+    /// `Err(NotFound)`; we don't want to tell callers which of the two it was.
+    ///
+    /// If the password matches but was hashed with an outdated method or weaker
+    /// parameters than are currently configured, it's rehashed on the way through.
+    pub fn get_validated(conn: &Connection, username: &str, password: &str) -> QueryResult<User> {
+        let mut user = users::table
+            .filter(users::username_normalized.eq(normalize_username(username)))
+            .first::<User>(conn)?;
+
+        let stored = match SaltyPassword::parse(&user.password) {
+            Some(stored) => stored,
+            None => return Err(NotFound),
+        };
+        if !stored.validate(password) {
+            return Err(NotFound);
+        }
+
+        if stored.needs_upgrade() {
+            // The user has already proven who they are; failing to upgrade
+            // their hash is no reason to turn them away. We'll try again
+            // next time.
+            let _ = user.set_password(conn, password);
+        }
+        Ok(user)
+    }
+
+    /// Check a plaintext password against this user's stored password
+    ///
+    /// A stored password which can't be parsed never matches anything.
+    pub fn check_password(&self, password: &str) -> bool {
+        SaltyPassword::parse(&self.password)
+            .map(|stored| stored.validate(password))
+            .unwrap_or(false)
+    }
+
+    /// Whether the named user must reset their password before they can sign in
+    ///
+    /// Only users whose stored password was corrupted are flagged, so no
+    /// password could sign them in anyway. Unknown users don't need to reset anything.
+    pub fn password_reset_required(conn: &Connection, username: &str) -> QueryResult<bool> {
+        users::table
+            .filter(users::username_normalized.eq(normalize_username(username)))
+            .select(users::password_reset_required)
+            .first::<bool>(conn)
+            .optional()
+            .map(|required| required.unwrap_or(false))
+    }
+
+    /// Whether a username is taken, or too like one which is, to be given to someone
+    ///
+    /// Usernames given up within the grace period count as taken. Names
+    /// which are taken only by the user `except`, if given, don't count.
+    pub fn username_taken(conn: &Connection, username: &str, except: Option<i32>) -> QueryResult<bool> {
+        use diesel::expression::dsl::exists;
+
+        let skeleton = username_skeleton(username);
+        let except = except.unwrap_or(-1);
+        let current: bool = diesel::select(exists(
+            users::table
+                .filter(users::username_skeleton.eq(&skeleton))
+                .filter(users::id.ne(except)),
+        )).get_result(conn)?;
+        if current {
+            return Ok(true);
+        }
+
+        let grace_start = Utc::now().naive_utc() - *USERNAME_GRACE_PERIOD;
+        diesel::select(exists(
+            former_usernames::table
+                .filter(former_usernames::username_skeleton.eq(&skeleton))
+                .filter(former_usernames::changed_at.gt(grace_start))
+                .filter(former_usernames::user_id.ne(except)),
+        )).get_result(conn)
+    }
+
+    /// Change this user's username, remembering the old one
+    ///
+    /// Changes which only affect case aren't remembered, as the old name
+    /// still finds this user anyway. Check the new name with `username::check`
+    /// and `username_taken` first.
+    pub fn rename(&mut self, conn: &Connection, new_username: &str) -> QueryResult<()> {
+        let new_normalized = normalize_username(new_username);
+        let new_skeleton = username_skeleton(new_username);
+
+        conn.transaction::<_, diesel::result::Error, _>(|| {
+            if new_normalized != self.username_normalized {
+                diesel::insert(&NewFormerUsername {
+                    user_id: self.id,
+                    username: &self.username,
+                    username_normalized: &self.username_normalized,
+                    username_skeleton: &self.username_skeleton,
+                }).into(former_usernames::table)
+                    .execute(conn)?;
+                // If the user is taking back a name they gave up, it mustn't
+                // redirect anywhere any more.
+                diesel::delete(
+                    former_usernames::table
+                        .filter(former_usernames::user_id.eq(self.id))
+                        .filter(former_usernames::username_normalized.eq(&new_normalized)),
+                ).execute(conn)?;
+            }
+            diesel::update(users::table.find(self.id))
+                .set((
+                    users::username.eq(new_username),
+                    users::username_normalized.eq(&new_normalized),
+                    users::username_skeleton.eq(&new_skeleton),
+                ))
+                .execute(conn)?;
+            Ok(())
+        })?;
+
+        self.username = new_username.to_string();
+        self.username_normalized = new_normalized;
+        self.username_skeleton = new_skeleton;
+        Ok(())
+    }
+
+    /// Find the user who most recently gave up a username, if they did so
+    /// within the grace period
+    pub fn find_by_former_username(conn: &Connection, username: &str) -> QueryResult<Option<User>> {
+        let grace_start = Utc::now().naive_utc() - *USERNAME_GRACE_PERIOD;
+        let former = former_usernames::table
+            .filter(former_usernames::username_normalized.eq(normalize_username(username)))
+            .filter(former_usernames::changed_at.gt(grace_start))
+            .order(former_usernames::changed_at.desc())
+            .first::<FormerUsername>(conn)
+            .optional()?;
+        match former {
+            Some(former) => users::table.find(former.user_id).first::<User>(conn).optional(),
+            None => Ok(None),
+        }
+    }
+
+    /// The key template of this user's image of the given kind, if they have one
+    pub fn image_key(&self, kind: ImageKind) -> Option<&String> {
+        match kind {
+            ImageKind::Avatar => self.avatar_key.as_ref(),
+            ImageKind::Banner => self.banner_key.as_ref(),
+        }
+    }
+
+    /// Store a new key template for this user's image of the given kind
+    pub fn set_image_key(
+        &mut self,
+        conn: &Connection,
+        kind: ImageKind,
+        key: Option<String>,
+    ) -> QueryResult<()> {
+        let target = users::table.find(self.id);
+        match kind {
+            ImageKind::Avatar => {
+                diesel::update(target).set(users::avatar_key.eq(&key)).execute(conn)?;
+                self.avatar_key = key;
+            }
+            ImageKind::Banner => {
+                diesel::update(target).set(users::banner_key.eq(&key)).execute(conn)?;
+                self.banner_key = key;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether this user has asked for their account to be deleted
+    ///
+    /// Deactivated accounts are hidden from everyone until they're purged.
+    pub fn is_deactivated(&self) -> bool {
+        self.deactivated_at.is_some()
+    }
+
+    /// Mark this user's account for deletion
+    pub fn deactivate(&mut self, conn: &Connection) -> QueryResult<()> {
+        let now = Utc::now().naive_utc();
+        diesel::update(users::table.find(self.id))
+            .set(users::deactivated_at.eq(now))
+            .execute(conn)?;
+        self.deactivated_at = Some(now);
+        Ok(())
+    }
+
+    /// Cancel the deletion of this user's account
+    pub fn reactivate(&mut self, conn: &Connection) -> QueryResult<()> {
+        diesel::update(users::table.find(self.id))
+            .set(users::deactivated_at.eq(None::<NaiveDateTime>))
+            .execute(conn)?;
+        self.deactivated_at = None;
+        Ok(())
+    }
+
+    /// This user's custom profile fields, in order
+    pub fn profile_fields(&self, conn: &Connection) -> QueryResult<Vec<ProfileField>> {
+        profile_fields::table
+            .filter(profile_fields::user_id.eq(self.id))
+            .order(profile_fields::position.asc())
+            .load(conn)
+    }
+
+    /// Replace this user's custom profile fields with the given labels and values
+    pub fn set_profile_fields(&self, conn: &Connection, fields: &[(String, String)]) -> QueryResult<()> {
+        conn.transaction::<_, diesel::result::Error, _>(|| {
+            diesel::delete(profile_fields::table.filter(profile_fields::user_id.eq(self.id)))
+                .execute(conn)?;
+            for (position, &(ref label, ref value)) in fields.iter().enumerate() {
+                diesel::insert(&NewProfileField {
+                    user_id: self.id,
+                    position: position as i32,
+                    label: label,
+                    value: value,
+                }).into(profile_fields::table)
+                    .execute(conn)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// How many users follow this user
+    pub fn follower_count(&self, conn: &Connection) -> QueryResult<i64> {
+        follows::table
+            .filter(follows::followed_id.eq(self.id))
+            .count()
+            .get_result(conn)
+    }
+
+    /// How many users this user follows
+    pub fn following_count(&self, conn: &Connection) -> QueryResult<i64> {
+        follows::table
+            .filter(follows::follower_id.eq(self.id))
+            .count()
+            .get_result(conn)
+    }
+
+    /// How many pings this user has made
+    pub fn ping_count(&self, conn: &Connection) -> QueryResult<i64> {
+        pings::table
+            .filter(pings::user_id.eq(self.id))
+            .filter(pings::deleted_at.is_null())
+            .count()
+            .get_result(conn)
+    }
+
+    /// Up to `limit` of this user's pings, moving away from a position
+    ///
+    /// Deleted pings are left out. See `page::paginate` for the order.
+    pub fn pings_page(
+        &self,
+        conn: &Connection,
+        position: Position,
+        limit: usize,
+    ) -> QueryResult<Vec<Ping>> {
+        let query = pings::table
+            .filter(pings::user_id.eq(self.id))
+            .filter(pings::deleted_at.is_null())
+            .into_boxed();
+        let query = match position {
+            Position::Newest => query.order((pings::timestamp.desc(), pings::id.desc())),
+            Position::Before(cursor) => {
+                query
+                    .filter(pings::timestamp.lt(cursor.timestamp).or(
+                        pings::timestamp.eq(cursor.timestamp).and(pings::id.lt(cursor.id)),
+                    ))
+                    .order((pings::timestamp.desc(), pings::id.desc()))
+            }
+            Position::After(cursor) => {
+                query
+                    .filter(pings::timestamp.gt(cursor.timestamp).or(
+                        pings::timestamp.eq(cursor.timestamp).and(pings::id.gt(cursor.id)),
+                    ))
+                    .order((pings::timestamp.asc(), pings::id.asc()))
+            }
+        };
+        query.limit(limit as i64).load::<Ping>(conn)
+    }
+
+    /// Start following the other user
+    ///
+    /// Returns whether this user wasn't following them already. That's left
+    /// to the unique index on follows to decide, so that two requests at once
+    /// can't both follow.
+    pub fn follow(&self, conn: &Connection, other: &User) -> QueryResult<bool> {
+        let inserted = diesel::insert(&NewFollow {
+            follower_id: self.id,
+            followed_id: other.id,
+        }).into(follows::table)
+            .execute(conn);
+        match inserted {
+            Ok(_) => Ok(true),
+            Err(DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Stop following the other user
+    ///
+    /// Returns whether this user was following them.
+    pub fn unfollow(&self, conn: &Connection, other: &User) -> QueryResult<bool> {
+        diesel::delete(follows::table.filter(
+            follows::follower_id.eq(self.id).and(follows::followed_id.eq(other.id)),
+        )).execute(conn)
+            .map(|deleted| deleted > 0)
+    }
+
+    /// Up to `limit` of the follows in one of this user's lists, moving away from a position
+    ///
+    /// See `page::paginate` for the order.
+    pub fn follows_page(
+        &self,
+        conn: &Connection,
+        list: FollowList,
+        position: Position,
+        limit: usize,
+    ) -> QueryResult<Vec<Follow>> {
+        let query = follows::table.into_boxed();
+        let query = match list {
+            FollowList::Followers => query.filter(follows::followed_id.eq(self.id)),
+            FollowList::Following => query.filter(follows::follower_id.eq(self.id)),
+        };
+        let query = match position {
+            Position::Newest => query.order((follows::timestamp.desc(), follows::id.desc())),
+            Position::Before(cursor) => {
+                query
+                    .filter(follows::timestamp.lt(cursor.timestamp).or(
+                        follows::timestamp.eq(cursor.timestamp).and(follows::id.lt(cursor.id)),
+                    ))
+                    .order((follows::timestamp.desc(), follows::id.desc()))
+            }
+            Position::After(cursor) => {
+                query
+                    .filter(follows::timestamp.gt(cursor.timestamp).or(
+                        follows::timestamp.eq(cursor.timestamp).and(follows::id.gt(cursor.id)),
+                    ))
+                    .order((follows::timestamp.asc(), follows::id.asc()))
+            }
+        };
+        query.limit(limit as i64).load::<Follow>(conn)
+    }
+
+    /// Whether either of this user and the other has blocked the other
+    pub fn blocks_between(&self, conn: &Connection, other: &User) -> QueryResult<bool> {
+        use diesel::expression::dsl::exists;
+
+        diesel::select(exists(blocks::table.filter(
+            (blocks::blocker_id.eq(self.id).and(blocks::blocked_id.eq(other.id))).or(
+                blocks::blocker_id.eq(other.id).and(blocks::blocked_id.eq(self.id)),
+            ),
+        ))).get_result(conn)
+    }
+
+    /// Hash and store a new password for this user
+    ///
+    /// This also clears any outstanding requirement to reset the password.
+    pub fn set_password(&mut self, conn: &Connection, password: &str) -> QueryResult<()> {
+        let hashed = SaltyPassword::new(password).to_string();
+        diesel::update(users::table.find(self.id))
+            .set((
+                users::password.eq(&hashed),
+                users::password_reset_required.eq(false),
+            ))
+            .execute(conn)?;
+        self.password = hashed;
+        self.password_reset_required = false;
+        Ok(())
     }
 }
 
@@ -37,18 +452,48 @@ pub struct NewUser {
     password: String,
     real_name: String,
     blurb: String,
+    email: Option<String>,
+    username_normalized: String,
+    username_skeleton: String,
+    joined_at: Option<NaiveDateTime>,
+    location: String,
+    website: String,
+    pronouns: String,
 }
 
 impl NewUser {
-    pub fn new(username: String, password: String, real_name: String, blurb: String) -> NewUser {
+    pub fn new(
+        username: String,
+        password: String,
+        real_name: String,
+        blurb: String,
+        email: Option<String>,
+    ) -> NewUser {
         NewUser {
+            username_normalized: normalize_username(&username),
+            username_skeleton: username_skeleton(&username),
             username: username,
             password: SaltyPassword::new(&password).to_string(),
             real_name: real_name,
             blurb: blurb,
+            email: email,
+            joined_at: Some(Utc::now().naive_utc()),
+            location: String::new(),
+            website: String::new(),
+            pronouns: String::new(),
         }
     }
 
+    /// Fill in the optional parts of the profile, beyond the real name and blurb
+    ///
+    /// `website` should already be normalized with `links::normalize`.
+    pub fn with_details(mut self, location: String, website: String, pronouns: String) -> NewUser {
+        self.location = location;
+        self.website = website;
+        self.pronouns = pronouns;
+        self
+    }
+
     pub fn insert(self, conn: &Connection) -> QueryResult<User> {
         use schema::users::dsl::*;
         diesel::insert(&self)
@@ -73,8 +518,51 @@ pub struct Ping {
     pub user_id: i32,
     pub timestamp: NaiveDateTime,
     pub content: String,
-    pub likes: u32,
-    pub echoes: u32,
+    pub likes: i32,
+    pub echoes: i32,
+    /// Set when the author deletes the ping; its content is cleared then
+    pub deleted_at: Option<NaiveDateTime>,
+}
+
+impl Ping {
+    /// Find the ping with a given id, if there is one
+    ///
+    /// Deleted pings are found too; check `is_deleted`.
+    pub fn find(conn: &Connection, id: i32) -> QueryResult<Option<Ping>> {
+        pings::table.find(id).first::<Ping>(conn).optional()
+    }
+
+    /// The user who made this ping
+    pub fn author(&self, conn: &Connection) -> QueryResult<User> {
+        users::table.find(self.user_id).first::<User>(conn)
+    }
+
+    /// This ping's position in a list of pings
+    pub fn cursor(&self) -> Cursor {
+        Cursor {
+            timestamp: self.timestamp,
+            id: self.id,
+        }
+    }
+
+    /// Whether the author has deleted this ping
+    pub fn is_deleted(&self) -> bool {
+        self.deleted_at.is_some()
+    }
+
+    /// Delete this ping
+    ///
+    /// The row stays, so that the ping is known to have existed, but its
+    /// content doesn't.
+    pub fn delete(&mut self, conn: &Connection) -> QueryResult<()> {
+        let now = Utc::now().naive_utc();
+        diesel::update(pings::table.find(self.id))
+            .set((pings::deleted_at.eq(now), pings::content.eq("")))
+            .execute(conn)?;
+        self.deleted_at = Some(now);
+        self.content = String::new();
+        Ok(())
+    }
 }
 
 #[derive(Insertable)]
@@ -84,6 +572,65 @@ pub struct NewPing<'a> {
     pub content: &'a str,
 }
 
+impl<'a> NewPing<'a> {
+    pub fn insert(&self, conn: &Connection) -> QueryResult<Ping> {
+        // SQLite can't return the inserted row, so fetch it back: it's the
+        // user's newest, as long as nobody else can insert in between.
+        conn.transaction::<_, diesel::result::Error, _>(|| {
+            diesel::insert(self).into(pings::table).execute(conn)?;
+            pings::table
+                .filter(pings::user_id.eq(self.user_id))
+                .order(pings::id.desc())
+                .first::<Ping>(conn)
+        })
+    }
+}
+
+/// `follower_id` follows `followed_id`
+#[derive(Identifiable, Queryable)]
+pub struct Follow {
+    pub id: i32,
+    pub follower_id: i32,
+    pub followed_id: i32,
+    pub timestamp: NaiveDateTime,
+}
+
+impl Follow {
+    /// This follow's position in a list of follows
+    pub fn cursor(&self) -> Cursor {
+        Cursor {
+            timestamp: self.timestamp,
+            id: self.id,
+        }
+    }
+}
+
+#[derive(Insertable)]
+#[table_name = "follows"]
+pub struct NewFollow {
+    pub follower_id: i32,
+    pub followed_id: i32,
+}
+
+/// Which of a user's lists of follows is meant
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FollowList {
+    /// The users who follow them
+    Followers,
+    /// The users they follow
+    Following,
+}
+
+impl FollowList {
+    /// Of a follow in this list, the id of the user other than the list's owner
+    pub fn other_id(&self, follow: &Follow) -> i32 {
+        match *self {
+            FollowList::Followers => follow.follower_id,
+            FollowList::Following => follow.followed_id,
+        }
+    }
+}
+
 #[derive(Identifiable, Queryable, Associations)]
 #[belongs_to(User)]
 #[table_name = "auth_tokens"]
@@ -91,12 +638,227 @@ pub struct Token {
     pub id: i32,
     pub user_id: i32,
     pub timestamp: NaiveDateTime,
-    pub key: String,
+    pub selector: String,
+    pub verifier: Vec<u8>,
+    pub device_name: String,
+    pub user_agent: Option<String>,
+    pub last_used: NaiveDateTime,
 }
 
 #[derive(Insertable)]
 #[table_name = "auth_tokens"]
 pub struct NewToken<'a> {
     pub user_id: i32,
-    pub key: &'a str,
+    pub selector: &'a str,
+    pub verifier: &'a [u8],
+    pub device_name: &'a str,
+    pub user_agent: Option<&'a str>,
+}
+
+#[derive(Identifiable, Queryable, Associations)]
+#[belongs_to(User)]
+#[table_name = "password_reset_tokens"]
+pub struct PasswordResetToken {
+    pub id: i32,
+    pub user_id: i32,
+    pub timestamp: NaiveDateTime,
+    pub selector: String,
+    pub verifier: Vec<u8>,
+}
+
+#[derive(Insertable)]
+#[table_name = "password_reset_tokens"]
+pub struct NewPasswordResetToken<'a> {
+    pub user_id: i32,
+    pub selector: &'a str,
+    pub verifier: &'a [u8],
+}
+
+#[derive(Identifiable, Queryable, Associations)]
+#[belongs_to(User)]
+#[table_name = "email_verification_tokens"]
+pub struct EmailVerificationToken {
+    pub id: i32,
+    pub user_id: i32,
+    pub timestamp: NaiveDateTime,
+    pub selector: String,
+    pub verifier: Vec<u8>,
+    pub email: String,
+}
+
+#[derive(Insertable)]
+#[table_name = "email_verification_tokens"]
+pub struct NewEmailVerificationToken<'a> {
+    pub user_id: i32,
+    pub selector: &'a str,
+    pub verifier: &'a [u8],
+    pub email: &'a str,
+}
+
+#[derive(Identifiable, Queryable, Associations)]
+#[belongs_to(User)]
+#[table_name = "recovery_codes"]
+pub struct RecoveryCode {
+    pub id: i32,
+    pub user_id: i32,
+    pub verifier: Vec<u8>,
+}
+
+#[derive(Insertable)]
+#[table_name = "recovery_codes"]
+pub struct NewRecoveryCode<'a> {
+    pub user_id: i32,
+    pub verifier: &'a [u8],
+}
+
+#[derive(Identifiable, Queryable, Associations)]
+#[belongs_to(User)]
+#[table_name = "login_challenges"]
+pub struct LoginChallengeToken {
+    pub id: i32,
+    pub user_id: i32,
+    pub timestamp: NaiveDateTime,
+    pub selector: String,
+    pub verifier: Vec<u8>,
+    pub device_name: String,
+    pub user_agent: Option<String>,
+    pub attempts: i32,
+}
+
+#[derive(Insertable)]
+#[table_name = "login_challenges"]
+pub struct NewLoginChallengeToken<'a> {
+    pub user_id: i32,
+    pub selector: &'a str,
+    pub verifier: &'a [u8],
+    pub device_name: &'a str,
+    pub user_agent: Option<&'a str>,
+}
+
+#[derive(Identifiable, Queryable)]
+#[table_name = "login_throttles"]
+pub struct Throttle {
+    pub id: i32,
+    pub subject: String,
+    pub failures: i32,
+    pub last_failure: NaiveDateTime,
+    pub blocked_until: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "login_throttles"]
+pub struct NewThrottle<'a> {
+    pub subject: &'a str,
+    pub failures: i32,
+    pub last_failure: NaiveDateTime,
+    pub blocked_until: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "failed_logins"]
+pub struct NewFailedLogin<'a> {
+    pub username: &'a str,
+    pub ip: Option<&'a str>,
+    pub user_agent: Option<&'a str>,
+    pub reason: &'a str,
+}
+
+#[derive(Identifiable, Queryable, Associations)]
+#[belongs_to(User)]
+#[table_name = "former_usernames"]
+pub struct FormerUsername {
+    pub id: i32,
+    pub user_id: i32,
+    pub username: String,
+    pub username_normalized: String,
+    pub username_skeleton: String,
+    pub changed_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "former_usernames"]
+pub struct NewFormerUsername<'a> {
+    pub user_id: i32,
+    pub username: &'a str,
+    pub username_normalized: &'a str,
+    pub username_skeleton: &'a str,
+}
+
+#[derive(Identifiable, Queryable, Associations)]
+#[belongs_to(User)]
+#[table_name = "profile_fields"]
+pub struct ProfileField {
+    pub id: i32,
+    pub user_id: i32,
+    pub position: i32,
+    pub label: String,
+    pub value: String,
+}
+
+#[derive(Insertable)]
+#[table_name = "profile_fields"]
+pub struct NewProfileField<'a> {
+    pub user_id: i32,
+    pub position: i32,
+    pub label: &'a str,
+    pub value: &'a str,
+}
+
+#[derive(Identifiable, Queryable, Associations)]
+#[belongs_to(User)]
+#[table_name = "data_exports"]
+pub struct DataExport {
+    pub id: i32,
+    pub user_id: i32,
+    /// One of `pending`, `ready` or `failed`
+    pub status: String,
+    pub requested_at: NaiveDateTime,
+    pub completed_at: Option<NaiveDateTime>,
+    /// The archive's name within `EXPORT_DIR`, once it's ready
+    pub file_name: Option<String>,
+    /// The archive's size in bytes, once it's ready
+    pub size: Option<i64>,
+}
+
+#[derive(Insertable)]
+#[table_name = "data_exports"]
+pub struct NewDataExport {
+    pub user_id: i32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db::establish_test_connection;
+
+    #[test]
+    fn test_flagged_legacy_user() {
+        let conn = establish_test_connection();
+        let user = NewUser::new(
+            String::from("Legacy"),
+            String::from("correct horse battery staple"),
+            String::new(),
+            String::new(),
+            None,
+        ).insert(&conn)
+            .unwrap();
+        // A legacy hash which lost some of its digits, flagged as the
+        // migration which found it would have
+        let corrupted = format!("$argon2${}${}$", "s".repeat(128), "ab".repeat(30));
+        diesel::update(users::table.find(user.id))
+            .set((
+                users::password.eq(&corrupted),
+                users::password_reset_required.eq(true),
+            ))
+            .execute(&conn)
+            .unwrap();
+
+        assert!(User::password_reset_required(&conn, "legacy").unwrap());
+        assert!(User::get_validated(&conn, "legacy", "correct horse battery staple").is_err());
+
+        let mut user = users::table.find(user.id).first::<User>(&conn).unwrap();
+        user.set_password(&conn, "another horse battery staple").unwrap();
+        assert!(!User::password_reset_required(&conn, "legacy").unwrap());
+        assert!(!User::password_reset_required(&conn, "nobody").unwrap());
+    }
 }
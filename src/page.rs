@@ -0,0 +1,314 @@
+//! Cursor pagination.
+//!
+//! Lists which grow at the front, like a user's pings, can't be paged by
+//! offset: whatever is added between two requests shifts everything along,
+//! so the second page repeats or skips items. Instead, each page is found
+//! relative to an item: `before` a cursor for older items, `after` one for
+//! newer. A cursor names an item by its timestamp and id, which together
+//! order every list newest first, even when timestamps tie.
+//!
+//! Cursors are opaque to clients, who follow the `Link` headers
+//! (RFC 5988) on each page rather than building URLs themselves.
+
+use base64;
+use chrono::NaiveDateTime;
+use rocket::http::Status as HttpStatus;
+use rocket::outcome::Outcome::*;
+use rocket::request::{Request, FromRequest, FormItems, Outcome};
+use rocket::response::{Response, Responder};
+
+/// The position of an item in a list
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cursor {
+    pub timestamp: NaiveDateTime,
+    pub id: i32,
+}
+
+impl Cursor {
+    /// Encode this cursor for use in a URL
+    pub fn encode(&self) -> String {
+        let raw = format!(
+            "{}.{}.{}",
+            self.timestamp.timestamp(),
+            self.timestamp.timestamp_subsec_nanos(),
+            self.id
+        );
+        base64::encode_config(raw.as_bytes(), base64::URL_SAFE_NO_PAD)
+    }
+
+    /// Decode a cursor produced by `encode`
+    pub fn decode(token: &str) -> Option<Cursor> {
+        let raw = base64::decode_config(token, base64::URL_SAFE_NO_PAD).ok()?;
+        let raw = String::from_utf8(raw).ok()?;
+        let mut parts = raw.split('.');
+        let seconds = parts.next()?.parse().ok()?;
+        let nanos = parts.next()?.parse().ok()?;
+        let id = parts.next()?.parse().ok()?;
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(Cursor {
+            timestamp: NaiveDateTime::from_timestamp_opt(seconds, nanos)?,
+            id: id,
+        })
+    }
+}
+
+/// Which page of a list is wanted
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Position {
+    /// The newest items
+    Newest,
+    /// The items just older than the cursor
+    Before(Cursor),
+    /// The items just newer than the cursor
+    After(Cursor),
+}
+
+/// A reason a page couldn't be found
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Problem {
+    BothDirections,
+    Malformed,
+}
+
+impl Problem {
+    /// A stable, machine-readable code which clients can use to explain the problem
+    pub fn code(&self) -> &'static str {
+        match *self {
+            Problem::BothDirections => "both_directions",
+            Problem::Malformed => "malformed_cursor",
+        }
+    }
+
+    /// A human-readable explanation of the problem
+    pub fn message(&self) -> &'static str {
+        match *self {
+            Problem::BothDirections => "Only one of `before` and `after` may be given",
+            Problem::Malformed => "Cursor is malformed",
+        }
+    }
+}
+
+impl Position {
+    /// Work out the position from the `before` and `after` query parameters
+    pub fn parse(before: Option<&str>, after: Option<&str>) -> Result<Position, Problem> {
+        let decode = |token| Cursor::decode(token).ok_or(Problem::Malformed);
+        match (before, after) {
+            (None, None) => Ok(Position::Newest),
+            (Some(before), None) => Ok(Position::Before(decode(before)?)),
+            (None, Some(after)) => Ok(Position::After(decode(after)?)),
+            (Some(_), Some(_)) => Err(Problem::BothDirections),
+        }
+    }
+}
+
+/// A page of a list, newest first, with the cursors of its neighbours
+#[derive(Debug, PartialEq)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Where the next page of older items starts, if there are any
+    pub older: Option<Cursor>,
+    /// Where the previous page of newer items starts, if there are any
+    pub newer: Option<Cursor>,
+}
+
+/// Make a page of at most `size` items from those fetched at a position
+///
+/// Callers fetch up to `size + 1` items, so that it's known whether there
+/// are more. Items are fetched moving away from the cursor: newest first for
+/// `Newest` and `Before`, but oldest first for `After`.
+pub fn paginate<T, F>(mut fetched: Vec<T>, position: Position, size: usize, cursor: F) -> Page<T>
+where
+    F: Fn(&T) -> Cursor,
+{
+    let more = fetched.len() > size;
+    fetched.truncate(size);
+    if let Position::After(_) = position {
+        fetched.reverse();
+    }
+
+    // Paging away from a cursor means there's at least the cursor's item
+    // to come back to.
+    let (more_older, more_newer) = match position {
+        Position::Newest => (more, false),
+        Position::Before(_) => (more, true),
+        Position::After(_) => (true, more),
+    };
+    let older = if more_older { fetched.last().map(&cursor) } else { None };
+    let newer = if more_newer { fetched.first().map(&cursor) } else { None };
+    Page {
+        items: fetched,
+        older: older,
+        newer: newer,
+    }
+}
+
+/// The `Link` header value for a page of the list at `path`, if it has neighbours
+pub fn link_header<T>(path: &str, page: &Page<T>) -> Option<String> {
+    let mut links = Vec::new();
+    if let Some(older) = page.older {
+        links.push(format!("<{}?before={}>; rel=\"next\"", path, older.encode()));
+    }
+    if let Some(newer) = page.newer {
+        links.push(format!("<{}?after={}>; rel=\"prev\"", path, newer.encode()));
+    }
+    if links.is_empty() {
+        None
+    } else {
+        Some(links.join(", "))
+    }
+}
+
+/// Request guard which extracts the path and the `before` and `after` query parameters
+///
+/// This guard never fails; it's up to the view to check the parameters.
+pub struct PageQuery {
+    pub path: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for PageQuery {
+    type Error = ();
+    fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
+        let mut query = PageQuery {
+            path: String::from(request.uri().path()),
+            before: None,
+            after: None,
+        };
+        for (key, value) in FormItems::from(request.uri().query().unwrap_or("")) {
+            match key.as_str() {
+                "before" => query.before = value.url_decode().ok(),
+                "after" => query.after = value.url_decode().ok(),
+                _ => {}
+            }
+        }
+        Success(query)
+    }
+}
+
+impl PageQuery {
+    /// Which page is wanted
+    pub fn position(&self) -> Result<Position, Problem> {
+        Position::parse(
+            self.before.as_ref().map(|b| b.as_str()),
+            self.after.as_ref().map(|a| a.as_str()),
+        )
+    }
+}
+
+/// Responder which adds a `Link` header, if there is one, to the wrapped response
+pub struct WithLinks<R>(pub Option<String>, pub R);
+
+impl<'r, R: Responder<'r>> Responder<'r> for WithLinks<R> {
+    fn respond_to(self, req: &Request) -> Result<Response<'r>, HttpStatus> {
+        let mut response = Response::build_from(self.1.respond_to(req)?);
+        if let Some(links) = self.0 {
+            response.raw_header("Link", links);
+        }
+        response.ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cursor(id: i32) -> Cursor {
+        Cursor {
+            timestamp: NaiveDateTime::from_timestamp(1_500_000_000 + id as i64, 0),
+            id: id,
+        }
+    }
+
+    fn page(fetched: Vec<i32>, position: Position) -> Page<i32> {
+        paginate(fetched, position, 3, |&id| cursor(id))
+    }
+
+    #[test]
+    fn test_cursor_round_trip() {
+        let with_nanos = Cursor {
+            timestamp: NaiveDateTime::from_timestamp(1_500_000_000, 123_456_789),
+            id: 42,
+        };
+        for c in &[cursor(1), with_nanos] {
+            assert_eq!(Cursor::decode(&c.encode()), Some(*c));
+        }
+    }
+
+    #[test]
+    fn test_cursor_malformed() {
+        assert_eq!(Cursor::decode(""), None);
+        assert_eq!(Cursor::decode("not a cursor"), None);
+        let extra = base64::encode_config(b"1.2.3.4", base64::URL_SAFE_NO_PAD);
+        assert_eq!(Cursor::decode(&extra), None);
+    }
+
+    #[test]
+    fn test_parse_position() {
+        let token = cursor(5).encode();
+        assert_eq!(Position::parse(None, None), Ok(Position::Newest));
+        assert_eq!(Position::parse(Some(&token), None), Ok(Position::Before(cursor(5))));
+        assert_eq!(Position::parse(None, Some(&token)), Ok(Position::After(cursor(5))));
+        assert_eq!(
+            Position::parse(Some(&token), Some(&token)),
+            Err(Problem::BothDirections)
+        );
+        assert_eq!(Position::parse(Some("nope"), None), Err(Problem::Malformed));
+    }
+
+    #[test]
+    fn test_newest() {
+        let p = page(vec![9, 8, 7, 6], Position::Newest);
+        assert_eq!(p.items, vec![9, 8, 7]);
+        assert_eq!(p.older, Some(cursor(7)));
+        assert_eq!(p.newer, None);
+
+        let p = page(vec![9, 8], Position::Newest);
+        assert_eq!(p.items, vec![9, 8]);
+        assert_eq!(p.older, None);
+        assert_eq!(p.newer, None);
+    }
+
+    #[test]
+    fn test_before() {
+        let p = page(vec![6, 5, 4, 3], Position::Before(cursor(7)));
+        assert_eq!(p.items, vec![6, 5, 4]);
+        assert_eq!(p.older, Some(cursor(4)));
+        assert_eq!(p.newer, Some(cursor(6)));
+
+        let p = page(vec![2, 1], Position::Before(cursor(3)));
+        assert_eq!(p.older, None);
+        assert_eq!(p.newer, Some(cursor(2)));
+    }
+
+    #[test]
+    fn test_after() {
+        // Fetched oldest first, but returned newest first
+        let p = page(vec![4, 5, 6, 7], Position::After(cursor(3)));
+        assert_eq!(p.items, vec![6, 5, 4]);
+        assert_eq!(p.older, Some(cursor(4)));
+        assert_eq!(p.newer, Some(cursor(6)));
+
+        let p = page(vec![8, 9], Position::After(cursor(7)));
+        assert_eq!(p.items, vec![9, 8]);
+        assert_eq!(p.older, Some(cursor(8)));
+        assert_eq!(p.newer, None);
+    }
+
+    #[test]
+    fn test_link_header() {
+        let p = page(vec![6, 5, 4, 3], Position::Before(cursor(7)));
+        assert_eq!(
+            link_header("/v1/users/sonar/pings", &p),
+            Some(format!(
+                "</v1/users/sonar/pings?before={}>; rel=\"next\", \
+                 </v1/users/sonar/pings?after={}>; rel=\"prev\"",
+                cursor(4).encode(),
+                cursor(6).encode()
+            ))
+        );
+        assert_eq!(link_header("/", &page(vec![1], Position::Newest)), None);
+    }
+}
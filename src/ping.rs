@@ -0,0 +1,98 @@
+//! Rules for pings.
+//!
+//! A ping's length is counted in grapheme clusters: what a reader would
+//! call characters. An emoji with a skin tone modifier, or a letter with a
+//! combining accent, counts once, however many bytes or code points it takes.
+
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Longest permissible ping, in grapheme clusters
+pub const MAX_LENGTH: usize = 140;
+
+/// A reason a ping was rejected
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Problem {
+    /// Has nothing in it but whitespace, if that
+    Empty,
+    TooLong,
+}
+
+impl Problem {
+    /// A stable, machine-readable code which clients can use to explain the problem
+    pub fn code(&self) -> &'static str {
+        match *self {
+            Problem::Empty => "empty",
+            Problem::TooLong => "too_long",
+        }
+    }
+
+    /// A human-readable explanation of the problem
+    pub fn message(&self) -> String {
+        match *self {
+            Problem::Empty => String::from("Ping must not be empty"),
+            Problem::TooLong => format!("Ping must be at most {} characters long", MAX_LENGTH),
+        }
+    }
+}
+
+/// The length of some text, as a reader would count it
+pub fn length(content: &str) -> usize {
+    content.graphemes(true).count()
+}
+
+/// Check whether proposed ping content is acceptable.
+///
+/// Leading and trailing whitespace is stripped before storing, so it
+/// doesn't count towards the length.
+pub fn check(content: &str) -> Result<(), Problem> {
+    let content = content.trim();
+    if content.is_empty() {
+        return Err(Problem::Empty);
+    }
+    if length(content) > MAX_LENGTH {
+        return Err(Problem::TooLong);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid() {
+        assert_eq!(check("Hello, sonar!"), Ok(()));
+        assert_eq!(check(&"a".repeat(MAX_LENGTH)), Ok(()));
+    }
+
+    #[test]
+    fn test_empty() {
+        assert_eq!(check(""), Err(Problem::Empty));
+        assert_eq!(check(" \t\n "), Err(Problem::Empty));
+        // No-break and ideographic spaces are whitespace too
+        assert_eq!(check("\u{a0}\u{3000}"), Err(Problem::Empty));
+    }
+
+    #[test]
+    fn test_too_long() {
+        assert_eq!(check(&"a".repeat(MAX_LENGTH + 1)), Err(Problem::TooLong));
+    }
+
+    #[test]
+    fn test_surrounding_whitespace_is_free() {
+        let content = format!("  {}  \n", "a".repeat(MAX_LENGTH));
+        assert_eq!(check(&content), Ok(()));
+    }
+
+    #[test]
+    fn test_counts_graphemes_not_bytes() {
+        // Each is one grapheme, but several code points
+        let waving = "👋🏽";
+        let accented = "e\u{301}";
+        assert_eq!(length(waving), 1);
+        assert_eq!(length(accented), 1);
+        assert_eq!(check(&waving.repeat(MAX_LENGTH)), Ok(()));
+        assert_eq!(check(&accented.repeat(MAX_LENGTH)), Ok(()));
+        assert_eq!(check(&waving.repeat(MAX_LENGTH + 1)), Err(Problem::TooLong));
+    }
+}
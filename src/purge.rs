@@ -0,0 +1,147 @@
+//! Purging of deleted accounts.
+//!
+//! Deleting an account only deactivates it, so that a user who changes their
+//! mind can sign in again to cancel. Once `ACCOUNT_DELETION_GRACE_PERIOD`
+//! has passed, a background thread removes the account and everything which
+//! refers to it. SQLite isn't enforcing our foreign keys, so nothing cascades
+//! on its own: each dependent table is cleared here explicitly.
+//!
+//! Likes are only counted on pings for now; when they're recorded per user,
+//! they must be purged here too.
+
+use std::thread;
+
+use chrono::{NaiveDateTime, Utc};
+use diesel;
+use diesel::Connection as ConnectionTrait;
+use diesel::prelude::*;
+use diesel::result::{Error, QueryResult};
+
+use auth::throttle::LoginThrottle;
+use config::{ACCOUNT_DELETION_GRACE_PERIOD, ACCOUNT_PURGE_INTERVAL};
+use db::{Connection, CONNECTION_POOL};
+use export;
+use media::{delete_stored, ImageKind};
+use models::User;
+use schema::{users, pings, auth_tokens, password_reset_tokens, email_verification_tokens,
+             recovery_codes, login_challenges, follows, blocks, former_usernames, profile_fields};
+
+/// Start the background thread which purges deleted accounts
+pub fn spawn() -> thread::JoinHandle<()> {
+    let interval = ACCOUNT_PURGE_INTERVAL.to_std().expect(
+        "ACCOUNT_PURGE_INTERVAL must not be negative",
+    );
+    thread::spawn(move || loop {
+        // Whatever fails is still due next time, so there's nothing to do but wait
+        let _ = purge_expired();
+        thread::sleep(interval);
+    })
+}
+
+/// What a pass over the deleted accounts achieved
+pub struct Purged {
+    /// How many accounts were purged
+    pub purged: usize,
+    /// The ids of the accounts which failed to purge, and why
+    pub failed: Vec<(i32, Error)>,
+}
+
+/// Purge every account whose deletion grace period has passed
+///
+/// An account which fails to purge is skipped, so that it can't hold up the
+/// rest; it's tried again next time.
+pub fn purge_expired() -> Result<Purged, &'static str> {
+    let connection = CONNECTION_POOL.get().map_err(
+        |_| "Couldn't get connection from pool",
+    )?;
+    let cutoff = Utc::now().naive_utc() - *ACCOUNT_DELETION_GRACE_PERIOD;
+
+    let expired = users::table
+        .filter(users::deactivated_at.lt(cutoff))
+        .load::<User>(&*connection)
+        .map_err(|_| "Failed to look up deleted accounts")?;
+    let mut result = Purged {
+        purged: 0,
+        failed: Vec::new(),
+    };
+    for user in expired.iter() {
+        match purge(&*connection, user, cutoff) {
+            Ok(true) => result.purged += 1,
+            Ok(false) => {}
+            Err(e) => result.failed.push((user.id, e)),
+        }
+    }
+    Ok(result)
+}
+
+/// Remove a user and everything which refers to them, if they're still
+/// deactivated since before `cutoff`
+///
+/// The user may have signed in, cancelling the deletion, since they were
+/// found; so the user is deleted first, only if they're still due, and the
+/// transaction holds off any sign in until the rest is gone. Returns whether
+/// the user was purged.
+fn purge(conn: &Connection, user: &User, cutoff: NaiveDateTime) -> QueryResult<bool> {
+    let purged = conn.transaction::<_, Error, _>(|| {
+        let deleted = diesel::delete(
+            users::table
+                .find(user.id)
+                .filter(users::deactivated_at.lt(cutoff)),
+        ).execute(conn)?;
+        if deleted == 0 {
+            return Err(Error::RollbackTransaction);
+        }
+
+        // Logins were attempted under the names the user went by, so
+        // gather those before their history goes.
+        let mut usernames = former_usernames::table
+            .filter(former_usernames::user_id.eq(user.id))
+            .select(former_usernames::username_normalized)
+            .load::<String>(conn)?;
+        usernames.push(user.username_normalized.clone());
+        LoginThrottle::forget(conn, &usernames)?;
+
+        diesel::delete(pings::table.filter(pings::user_id.eq(user.id)))
+            .execute(conn)?;
+        diesel::delete(auth_tokens::table.filter(auth_tokens::user_id.eq(user.id)))
+            .execute(conn)?;
+        diesel::delete(password_reset_tokens::table.filter(
+            password_reset_tokens::user_id.eq(user.id),
+        )).execute(conn)?;
+        diesel::delete(email_verification_tokens::table.filter(
+            email_verification_tokens::user_id.eq(user.id),
+        )).execute(conn)?;
+        diesel::delete(recovery_codes::table.filter(recovery_codes::user_id.eq(user.id)))
+            .execute(conn)?;
+        diesel::delete(login_challenges::table.filter(
+            login_challenges::user_id.eq(user.id),
+        )).execute(conn)?;
+        diesel::delete(follows::table.filter(
+            follows::follower_id.eq(user.id).or(follows::followed_id.eq(user.id)),
+        )).execute(conn)?;
+        diesel::delete(blocks::table.filter(
+            blocks::blocker_id.eq(user.id).or(blocks::blocked_id.eq(user.id)),
+        )).execute(conn)?;
+        diesel::delete(former_usernames::table.filter(
+            former_usernames::user_id.eq(user.id),
+        )).execute(conn)?;
+        diesel::delete(profile_fields::table.filter(profile_fields::user_id.eq(user.id)))
+            .execute(conn)?;
+        export::remove_for(conn, user.id, None)?;
+        Ok(())
+    });
+    match purged {
+        Ok(()) => {}
+        Err(Error::RollbackTransaction) => return Ok(false),
+        Err(e) => return Err(e),
+    }
+
+    // Only once nothing refers to the images any more
+    if let Some(ref key) = user.avatar_key {
+        delete_stored(ImageKind::Avatar, key);
+    }
+    if let Some(ref key) = user.banner_key {
+        delete_stored(ImageKind::Banner, key);
+    }
+    Ok(true)
+}
@@ -268,10 +268,20 @@ bare_status!(
     /// Sets the status of the response to 428 (Precondition Required)
     PreconditionRequired
 );
-bare_status!(
-    /// Sets the status of the response to 429 (Too Many Requests)
-    TooManyRequests
-);
+/// Sets the status of the response to 429 (Too Many Requests)
+///
+/// Like `Unauthorized`, this takes two parameters: the first is the number
+/// of seconds the client should wait before trying again, which is sent
+/// in the `Retry-After` header, and the second is the responder.
+pub struct TooManyRequests<R>(pub u64, pub R);
+impl<'r, R: Responder<'r>> Responder<'r> for TooManyRequests<R> {
+    fn respond_to(self, req: &Request) -> Result<Response<'r>, HttpStatus> {
+        Response::build_from(self.1.respond_to(req)?)
+            .status(HttpStatus::TooManyRequests)
+            .raw_header("Retry-After", self.0.to_string())
+            .ok()
+    }
+}
 bare_status!(
     /// Sets the status of the response to 431 (Request Header Fields Too Large)
     RequestHeaderFieldsTooLarge
@@ -0,0 +1,152 @@
+//! Rules for usernames.
+//!
+//! Usernames appear in URLs, so they're restricted to ASCII letters, digits
+//! and underscores. That rules out Unicode lookalikes entirely; the lookalikes
+//! which remain within ASCII, such as `0` and `o`, are caught by comparing
+//! skeletons.
+
+use config::RESERVED_USERNAMES;
+
+/// Shortest permissible username
+pub const MIN_LENGTH: usize = 3;
+/// Longest permissible username
+pub const MAX_LENGTH: usize = 30;
+
+/// Characters and sequences which look alike, and what each folds to
+///
+/// These are the ASCII entries from the Unicode confusables list (UTS #39).
+/// They're applied in order, after lowercasing. This must match the
+/// `normalize_usernames` migration, which computed skeletons for existing users.
+const CONFUSABLES: &'static [(&'static str, &'static str)] = &[
+    ("0", "o"),
+    ("1", "l"),
+    ("i", "l"),
+    ("rn", "m"),
+    ("vv", "w"),
+    ("cl", "d"),
+];
+
+/// A reason a username was rejected
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Problem {
+    TooShort,
+    TooLong,
+    /// Contains something other than ASCII letters, digits and underscores
+    InvalidCharacters,
+    /// Is, or looks like, a name reserved for the site itself
+    Reserved,
+}
+
+impl Problem {
+    /// A stable, machine-readable code which clients can use to explain the problem
+    pub fn code(&self) -> &'static str {
+        match *self {
+            Problem::TooShort => "too_short",
+            Problem::TooLong => "too_long",
+            Problem::InvalidCharacters => "invalid_characters",
+            Problem::Reserved => "reserved",
+        }
+    }
+
+    /// A human-readable explanation of the problem
+    pub fn message(&self) -> String {
+        match *self {
+            Problem::TooShort => format!("Username must be at least {} characters long", MIN_LENGTH),
+            Problem::TooLong => format!("Username must be at most {} characters long", MAX_LENGTH),
+            Problem::InvalidCharacters => String::from(
+                "Username may only contain letters, digits and underscores",
+            ),
+            Problem::Reserved => String::from("Username is reserved; pick another"),
+        }
+    }
+}
+
+/// Check whether a proposed username is acceptable.
+///
+/// This doesn't check whether anyone else already has it, or something like it.
+pub fn check(username: &str) -> Result<(), Problem> {
+    if !username.chars().all(
+        |c| c.is_ascii_alphanumeric() || c == '_',
+    )
+    {
+        return Err(Problem::InvalidCharacters);
+    }
+    // All ASCII, so bytes are characters
+    if username.len() < MIN_LENGTH {
+        return Err(Problem::TooShort);
+    }
+    if username.len() > MAX_LENGTH {
+        return Err(Problem::TooLong);
+    }
+
+    let proposed = skeleton(username);
+    if RESERVED_USERNAMES.iter().any(
+        |reserved| skeleton(reserved) == proposed,
+    )
+    {
+        return Err(Problem::Reserved);
+    }
+    Ok(())
+}
+
+/// The form of a username which is looked up and kept unique
+///
+/// Usernames are displayed as their owners typed them, but `Sonar` and
+/// `sonar` are the same user.
+pub fn normalize(username: &str) -> String {
+    username.to_ascii_lowercase()
+}
+
+/// The form of a username with lookalike characters folded together
+///
+/// No two users may have the same skeleton, so nobody can impersonate
+/// `sonar` as `s0nar`.
+pub fn skeleton(username: &str) -> String {
+    CONFUSABLES.iter().fold(
+        normalize(username),
+        |folded, &(from, to)| folded.replace(from, to),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid() {
+        assert_eq!(check("peter_gn"), Ok(()));
+        assert_eq!(check("Peter1985"), Ok(()));
+    }
+
+    #[test]
+    fn test_length() {
+        assert_eq!(check(""), Err(Problem::TooShort));
+        assert_eq!(check("ab"), Err(Problem::TooShort));
+        assert_eq!(check(&"a".repeat(MAX_LENGTH)), Ok(()));
+        assert_eq!(check(&"a".repeat(MAX_LENGTH + 1)), Err(Problem::TooLong));
+    }
+
+    #[test]
+    fn test_invalid_characters() {
+        for username in ["with space", "a/b", "dot.name", "ünïcödé", "аdmin_cyrillic"].iter() {
+            assert_eq!(check(username), Err(Problem::InvalidCharacters), "{}", username);
+        }
+    }
+
+    #[test]
+    fn test_reserved() {
+        assert_eq!(check("admin"), Err(Problem::Reserved));
+        assert_eq!(check("ADMIN"), Err(Problem::Reserved));
+        assert_eq!(check("adm1n"), Err(Problem::Reserved));
+        assert_eq!(check("settings"), Err(Problem::Reserved));
+    }
+
+    #[test]
+    fn test_skeleton() {
+        assert_eq!(skeleton("Sonar"), skeleton("s0nar"));
+        assert_eq!(skeleton("modern"), skeleton("modem"));
+        assert_eq!(skeleton("Bill"), skeleton("B1lI"));
+        assert_eq!(skeleton("Cl1ntOn_vv"), "dlnton_w");
+        assert_ne!(skeleton("alice"), skeleton("bob"));
+    }
+}
@@ -0,0 +1,287 @@
+//! Views which export a user's personal data.
+//!
+//! Asking for an export starts building an archive in the background; the
+//! client polls until it's ready, then downloads it. Downloads honour
+//! `Range`, so that an interrupted one can be resumed, and are streamed
+//! from disk rather than read into memory first.
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+
+use auth::token::TokenAuth;
+use db::DB;
+use export;
+use models::DataExport;
+use rocket::http::ContentType;
+use rocket::http::Status as HttpStatus;
+use rocket::outcome::Outcome::*;
+use rocket::request::{Request, FromRequest, Outcome};
+use rocket::response::{Body, Response, Responder};
+use rocket_contrib::{Json, Value};
+use status::Status;
+
+/// Where the archive of a ready export can be downloaded from
+const ARCHIVE_URL: &'static str = "/v1/me/export/archive";
+
+/// Request guard which extracts the `Range` header, if any
+///
+/// This guard never fails; a missing header simply produces `None`.
+struct RangeHeader(Option<String>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for RangeHeader {
+    type Error = ();
+    fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
+        Success(RangeHeader(
+            request.headers().get_one("Range").map(String::from),
+        ))
+    }
+}
+
+/// What part of a resource a `Range` header asks for
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ByteRange {
+    Whole,
+    /// The first and last bytes wanted, inclusive
+    Part(u64, u64),
+    /// A range which lies entirely past the end of the resource
+    Unsatisfiable,
+}
+
+/// Work out what part of a resource of the given length a `Range` header asks for
+///
+/// Only single byte ranges are supported. Anything else, including a header
+/// which can't be parsed, gets the whole resource, as RFC 7233 permits.
+fn parse_range(header: Option<&str>, length: u64) -> ByteRange {
+    let spec = match header {
+        Some(header) if header.starts_with("bytes=") => header["bytes=".len()..].trim(),
+        _ => return ByteRange::Whole,
+    };
+    if spec.contains(',') {
+        return ByteRange::Whole;
+    }
+    let dash = match spec.find('-') {
+        Some(dash) => dash,
+        None => return ByteRange::Whole,
+    };
+    let (first, last) = (&spec[..dash], &spec[dash + 1..]);
+
+    if first.is_empty() {
+        // A suffix: the last so many bytes
+        return match last.parse::<u64>() {
+            Ok(0) => ByteRange::Unsatisfiable,
+            Ok(_) if length == 0 => ByteRange::Unsatisfiable,
+            Ok(suffix) => ByteRange::Part(length.saturating_sub(suffix), length - 1),
+            Err(_) => ByteRange::Whole,
+        };
+    }
+    let first = match first.parse::<u64>() {
+        Ok(first) => first,
+        Err(_) => return ByteRange::Whole,
+    };
+    let last = if last.is_empty() {
+        None
+    } else {
+        match last.parse::<u64>() {
+            Ok(last) if last >= first => Some(last),
+            _ => return ByteRange::Whole,
+        }
+    };
+    if first >= length {
+        return ByteRange::Unsatisfiable;
+    }
+    ByteRange::Part(first, last.map_or(length - 1, |last| last.min(length - 1)))
+}
+
+/// The response to a download: an archive, or an explanation of why there isn't one
+enum Download {
+    Error(Json<Value>),
+    /// `count` bytes of an archive, starting at `first`, out of `length` in all
+    ///
+    /// The file must already be positioned at `first`.
+    Archive {
+        file: File,
+        first: u64,
+        count: u64,
+        length: u64,
+    },
+    /// The range asked for lies past the end of an archive of this length
+    Unsatisfiable(u64),
+}
+
+impl<'r> Responder<'r> for Download {
+    fn respond_to(self, req: &Request) -> Result<Response<'r>, HttpStatus> {
+        match self {
+            Download::Error(json) => json.respond_to(req),
+            Download::Archive {
+                file,
+                first,
+                count,
+                length,
+            } => {
+                let mut response = Response::build();
+                response
+                    .header(ContentType::new("application", "zip"))
+                    .raw_header("Accept-Ranges", "bytes")
+                    .raw_header(
+                        "Content-Disposition",
+                        "attachment; filename=\"sonar-export.zip\"",
+                    );
+                if count != length {
+                    response.raw_header(
+                        "Content-Range",
+                        format!("bytes {}-{}/{}", first, first + count - 1, length),
+                    );
+                }
+                response.raw_body(Body::Sized(file.take(count), count)).ok()
+            }
+            Download::Unsatisfiable(length) => {
+                Response::build_from(
+                    Json(json!({"error": "Requested range is past the end of the archive"}))
+                        .respond_to(req)?,
+                ).raw_header("Content-Range", format!("bytes */{}", length))
+                    .ok()
+            }
+        }
+    }
+}
+
+fn serialize_export(data_export: &DataExport) -> Json<Value> {
+    let expires = export::expires_at(data_export);
+    Json(json!({
+        "status": data_export.status,
+        "requested": data_export.requested_at,
+        "completed": data_export.completed_at,
+        "size": data_export.size,
+        "expires": expires,
+        "download": expires.map(|_| ARCHIVE_URL),
+    }))
+}
+
+/// View with which a user asks for an export of all their personal data
+///
+/// The archive is built in the background; poll `GET /me/export` to find
+/// out when it's ready. A new export replaces any earlier one.
+#[post("/me/export")]
+fn request_export(auth: TokenAuth) -> Status<Json<Value>> {
+    let requested = or_return!(export::request(&auth.user), |e| {
+        status!(InternalServerError, Json(json!({ "error": e })))
+    });
+    match requested {
+        Some(data_export) => status!(Accepted, Some(serialize_export(&data_export))),
+        None => {
+            status!(
+                Conflict,
+                Json(json!({"error": "An export is already being built"}))
+            )
+        }
+    }
+}
+
+/// View with which a user checks on their latest export
+#[get("/me/export")]
+fn get_export(auth: TokenAuth, db: DB) -> Status<Json<Value>> {
+    match export::latest(db.conn(), &auth.user) {
+        Ok(Some(data_export)) => status!(Ok, serialize_export(&data_export)),
+        Ok(None) => {
+            status!(
+                NotFound,
+                Json(json!({"error": "You haven't asked for an export"}))
+            )
+        }
+        Err(_) => DB_FAILURE!(),
+    }
+}
+
+/// View with which a user downloads their latest export
+///
+/// Send `Range: bytes={first}-` to resume an interrupted download; the
+/// response is then 206, with only the bytes asked for.
+#[get("/me/export/archive")]
+fn download_export(range: RangeHeader, auth: TokenAuth, db: DB) -> Status<Download> {
+    let failure = || {
+        status!(
+            InternalServerError,
+            Download::Error(Json(json!({"error": "Failed to read export archive"})))
+        )
+    };
+    let not_ready = || {
+        status!(
+            NotFound,
+            Download::Error(Json(json!({"error": "No export is ready for download"})))
+        )
+    };
+
+    let data_export = match export::latest_ready(db.conn(), &auth.user) {
+        Ok(Some(data_export)) => data_export,
+        Ok(None) => return not_ready(),
+        Err(_) => {
+            return status!(
+                InternalServerError,
+                Download::Error(Json(json!({"error": "Failed to connect to backing database"})))
+            )
+        }
+    };
+    let mut file = match export::open(&data_export) {
+        Ok(Some(file)) => file,
+        Ok(None) => return not_ready(),
+        Err(_) => return failure(),
+    };
+    let length = match file.metadata() {
+        Ok(metadata) => metadata.len(),
+        Err(_) => return failure(),
+    };
+
+    let (first, count) = match parse_range(range.0.as_ref().map(|r| r.as_str()), length) {
+        ByteRange::Whole => (0, length),
+        ByteRange::Part(first, last) => (first, last + 1 - first),
+        ByteRange::Unsatisfiable => {
+            return status!(RangeNotSatisfiable, Download::Unsatisfiable(length))
+        }
+    };
+    if file.seek(SeekFrom::Start(first)).is_err() {
+        return failure();
+    }
+
+    let partial = count < length;
+    let download = Download::Archive {
+        file: file,
+        first: first,
+        count: count,
+        length: length,
+    };
+    if partial {
+        status!(PartialContent, download)
+    } else {
+        status!(Ok, download)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_range() {
+        assert_eq!(parse_range(None, 100), ByteRange::Whole);
+        assert_eq!(parse_range(Some("items=0-5"), 100), ByteRange::Whole);
+        assert_eq!(parse_range(Some("bytes=0-5,10-20"), 100), ByteRange::Whole);
+        assert_eq!(parse_range(Some("bytes=nonsense"), 100), ByteRange::Whole);
+        assert_eq!(parse_range(Some("bytes=5-2"), 100), ByteRange::Whole);
+    }
+
+    #[test]
+    fn test_ranges() {
+        assert_eq!(parse_range(Some("bytes=0-9"), 100), ByteRange::Part(0, 9));
+        assert_eq!(parse_range(Some("bytes=50-"), 100), ByteRange::Part(50, 99));
+        assert_eq!(parse_range(Some("bytes=90-200"), 100), ByteRange::Part(90, 99));
+        assert_eq!(parse_range(Some("bytes=-10"), 100), ByteRange::Part(90, 99));
+        assert_eq!(parse_range(Some("bytes=-500"), 100), ByteRange::Part(0, 99));
+    }
+
+    #[test]
+    fn test_unsatisfiable() {
+        assert_eq!(parse_range(Some("bytes=100-"), 100), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range(Some("bytes=-0"), 100), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range(Some("bytes=-5"), 0), ByteRange::Unsatisfiable);
+    }
+}
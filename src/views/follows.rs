@@ -0,0 +1,150 @@
+//! Views which follow and unfollow users, and list who follows whom.
+//!
+//! Lists of followers and followed users are paginated by cursor, newest
+//! follow first, just like lists of pings.
+
+use auth::token::TokenAuth;
+use config::PAGE_SIZE;
+use db::DB;
+use diesel::prelude::*;
+use models::{Follow, FollowList, User};
+use page::{link_header, paginate, PageQuery, WithLinks};
+use rocket_contrib::{Json, Value};
+use schema::users;
+use status::Status;
+use super::{bad_page_query, find_visible_user, serialize_user};
+
+/// View with which a user follows another
+#[put("/users/<username>/follow")]
+fn follow_user(username: String, auth: TokenAuth, db: DB) -> Status<Json<Value>> {
+    let conn = db.conn();
+    let user = or_return!(find_visible_user(conn, &username, Some(&auth.user)), |e| e);
+    if user.id == auth.user.id {
+        return status!(
+            BadRequest,
+            Json(json!({"error": "You can't follow yourself"}))
+        );
+    }
+
+    match auth.user.follow(conn, &user) {
+        Ok(true) => status!(NoContent),
+        Ok(false) => {
+            status!(
+                Conflict,
+                Json(json!({"error": "You already follow this user"}))
+            )
+        }
+        Err(_) => DB_FAILURE!(),
+    }
+}
+
+/// View with which a user stops following another
+#[delete("/users/<username>/follow")]
+fn unfollow_user(username: String, auth: TokenAuth, db: DB) -> Status<Json<Value>> {
+    let conn = db.conn();
+    let user = or_return!(find_visible_user(conn, &username, Some(&auth.user)), |e| e);
+    match auth.user.unfollow(conn, &user) {
+        Ok(true) => status!(NoContent),
+        Ok(false) => {
+            status!(
+                NotFound,
+                Json(json!({"error": "You don't follow this user"}))
+            )
+        }
+        Err(_) => DB_FAILURE!(),
+    }
+}
+
+/// List a page of one of a user's lists of follows
+///
+/// Users who are deactivated, or who have blocked or been blocked by the
+/// viewer, are left out; so a page may hold fewer than `PAGE_SIZE` users
+/// even when there are more to come.
+fn list_follows(
+    list: FollowList,
+    username: &str,
+    page_query: PageQuery,
+    auth: Option<TokenAuth>,
+    db: DB,
+) -> WithLinks<Status<Json<Value>>> {
+    let conn = db.conn();
+    let position = or_return!(page_query.position(), |problem| {
+        WithLinks(None, bad_page_query(problem))
+    });
+    let viewer = auth.as_ref().map(|auth| &auth.user);
+    let user = or_return!(find_visible_user(conn, username, viewer), |e| {
+        WithLinks(None, e)
+    });
+
+    let fetched = or_return!(
+        user.follows_page(conn, list, position, *PAGE_SIZE + 1),
+        |_| WithLinks(None, DB_FAILURE!())
+    );
+    let page = paginate(fetched, position, *PAGE_SIZE, Follow::cursor);
+    let others = or_return!(
+        users::table
+            .filter(users::id.eq_any(
+                page.items.iter().map(|follow| list.other_id(follow)).collect::<Vec<_>>(),
+            ))
+            .load::<User>(conn),
+        |_| WithLinks(None, DB_FAILURE!())
+    );
+
+    let mut users_data = Vec::new();
+    for follow in page.items.iter() {
+        let other = match others.iter().find(|other| other.id == list.other_id(follow)) {
+            Some(other) => other,
+            None => continue,
+        };
+        if other.is_deactivated() {
+            continue;
+        }
+        if let Some(viewer) = viewer {
+            match viewer.blocks_between(conn, other) {
+                Ok(false) => {}
+                Ok(true) => continue,
+                Err(_) => return WithLinks(None, DB_FAILURE!()),
+            }
+        }
+        let mut other_data = or_return!(serialize_user(conn, other), |e| WithLinks(None, e)).0;
+        other_data["followed_at"] = json!(follow.timestamp);
+        users_data.push(other_data);
+    }
+
+    WithLinks(
+        link_header(&page_query.path, &page),
+        status!(
+            Ok,
+            Json(json!({
+                "username": user.username,
+                "users": users_data,
+            }))
+        ),
+    )
+}
+
+/// View with which to list the users who follow a user, most recent first
+///
+/// The list is paginated like a user's pings.
+#[get("/users/<username>/followers")]
+fn get_followers(
+    username: String,
+    page_query: PageQuery,
+    auth: Option<TokenAuth>,
+    db: DB,
+) -> WithLinks<Status<Json<Value>>> {
+    list_follows(FollowList::Followers, &username, page_query, auth, db)
+}
+
+/// View with which to list the users whom a user follows, most recently followed first
+///
+/// The list is paginated like a user's pings.
+#[get("/users/<username>/following")]
+fn get_following(
+    username: String,
+    page_query: PageQuery,
+    auth: Option<TokenAuth>,
+    db: DB,
+) -> WithLinks<Status<Json<Value>>> {
+    list_follows(FollowList::Following, &username, page_query, auth, db)
+}
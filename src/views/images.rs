@@ -0,0 +1,261 @@
+//! Views which manage profile images.
+//!
+//! Avatars and banners are uploaded as `multipart/form-data`, with the
+//! image in a field called `image`. Each upload is checked, re-encoded in
+//! every size its kind needs, and written to the blob store; the user's
+//! profile then points at the new files.
+
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+
+use auth::token::{random_string, TokenAuth};
+use blob::BLOB_STORE;
+use config::{BLOB_DIR, IMAGE_MAX_BYTES, IMAGE_MAX_PIXELS};
+use db::DB;
+use media::{delete_stored, extension, process, sniff, ImageKind, Rejection, SIZE_PLACEHOLDER};
+use multipart::server::Multipart;
+use rocket::Data;
+use rocket::outcome::Outcome::*;
+use rocket::request::{Request, FromRequest, Outcome};
+use rocket::response::NamedFile;
+use rocket_contrib::{Json, Value};
+use status::Status;
+use super::serialize_user;
+
+/// The form field an upload's image is sent in
+const IMAGE_FIELD: &'static str = "image";
+
+/// How long is the random part of a stored image's key
+///
+/// A fresh key for every upload means clients and caches never see a stale image.
+const KEY_NONCE_LENGTH: usize = 16;
+
+/// Request guard which extracts the boundary of a `multipart/form-data` body
+///
+/// This guard never fails; any other content type simply produces `None`.
+struct MultipartBoundary(Option<String>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for MultipartBoundary {
+    type Error = ();
+    fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
+        let boundary = request.content_type().and_then(|content_type| {
+            if content_type.top() == "multipart" && content_type.sub() == "form-data" {
+                content_type.params().find(|&(key, _)| key == "boundary").map(
+                    |(_, value)| String::from(value),
+                )
+            } else {
+                None
+            }
+        });
+        Success(MultipartBoundary(boundary))
+    }
+}
+
+/// The URLs of each size of a stored image, or null if there isn't one
+pub fn image_urls(kind: ImageKind, key: Option<&String>) -> Value {
+    match key {
+        None => Value::Null,
+        Some(key) => {
+            Value::Object(
+                kind.sizes()
+                    .iter()
+                    .map(|&(label, _, _)| {
+                        (
+                            String::from(label),
+                            json!(BLOB_STORE.url(&key.replace(SIZE_PLACEHOLDER, label))),
+                        )
+                    })
+                    .collect(),
+            )
+        }
+    }
+}
+
+/// Pull the image and its claimed content type out of a multipart body
+fn read_image_field(body: Vec<u8>, boundary: String) -> Option<(Vec<u8>, Option<String>)> {
+    let mut multipart = Multipart::with_body(Cursor::new(body), boundary);
+    while let Ok(Some(mut field)) = multipart.read_entry() {
+        if &*field.headers.name == IMAGE_FIELD {
+            let content_type = field.headers.content_type.as_ref().map(|mime| mime.to_string());
+            let mut image = Vec::new();
+            return match field.data.read_to_end(&mut image) {
+                Ok(_) => Some((image, content_type)),
+                Err(_) => None,
+            };
+        }
+    }
+    None
+}
+
+fn upload_image(
+    kind: ImageKind,
+    username: &str,
+    boundary: MultipartBoundary,
+    data: Data,
+    auth: TokenAuth,
+    db: DB,
+) -> Status<Json<Value>> {
+    let conn = db.conn();
+    let mut user = auth.user;
+    if !user.is_named(username) {
+        return status!(
+            Forbidden,
+            Json(json!({"error": format!("You may only change your own {}", kind.name())}))
+        );
+    }
+    let boundary = match boundary.0 {
+        Some(boundary) => boundary,
+        None => {
+            return status!(
+                UnsupportedMediaType,
+                Json(json!({"error": "Images must be uploaded as multipart/form-data"}))
+            )
+        }
+    };
+
+    // Read one byte more than we'll accept, so that we can tell if there was more
+    let mut body = Vec::new();
+    or_return!(
+        data.open().take(*IMAGE_MAX_BYTES + 1).read_to_end(&mut body),
+        |_| status!(BadRequest, Json(json!({"error": "Couldn't read upload"})))
+    );
+    if body.len() as u64 > *IMAGE_MAX_BYTES {
+        return status!(
+            PayloadTooLarge,
+            Json(json!({
+                "error": format!("Images must be at most {} bytes", *IMAGE_MAX_BYTES),
+            }))
+        );
+    }
+
+    let (image, content_type) = match read_image_field(body, boundary) {
+        Some(field) => field,
+        None => {
+            return status!(
+                BadRequest,
+                Json(json!({"error": format!("Upload must have an `{}` field", IMAGE_FIELD)}))
+            )
+        }
+    };
+    let rejected = |rejection| match rejection {
+        Rejection::UnsupportedType => {
+            status!(
+                UnsupportedMediaType,
+                Json(json!({"error": "Images must be PNG, JPEG or GIF"}))
+            )
+        }
+        Rejection::Corrupt => {
+            status!(
+                BadRequest,
+                Json(json!({"error": "Image could not be decoded"}))
+            )
+        }
+        Rejection::TooLarge => {
+            status!(
+                PayloadTooLarge,
+                Json(json!({
+                    "error": format!("Images may have at most {} pixels", *IMAGE_MAX_PIXELS),
+                }))
+            )
+        }
+    };
+    let format = or_return!(sniff(&image, content_type.as_ref().map(|t| t.as_str())), rejected);
+    let renditions = or_return!(process(kind, &image, format), rejected);
+
+    let nonce = or_return!(random_string(KEY_NONCE_LENGTH), |e| {
+        status!(InternalServerError, Json(json!({ "error": e })))
+    });
+    let key = format!(
+        "{}/{}/{}-{}.{}",
+        kind.name(),
+        user.id,
+        nonce,
+        SIZE_PLACEHOLDER,
+        extension(format)
+    );
+    for rendition in renditions.iter() {
+        or_return!(
+            BLOB_STORE.put(&key.replace(SIZE_PLACEHOLDER, rendition.label), &rendition.data),
+            |_| {
+                delete_stored(kind, &key);
+                status!(
+                    InternalServerError,
+                    Json(json!({"error": "Failed to store image"}))
+                )
+            }
+        );
+    }
+
+    let old_key = user.image_key(kind).cloned();
+    or_return!(user.set_image_key(conn, kind, Some(key)), |_| DB_FAILURE!());
+    if let Some(old_key) = old_key {
+        delete_stored(kind, &old_key);
+    }
+    status!(Ok, or_return!(serialize_user(conn, &user), |e| e))
+}
+
+fn remove_image(kind: ImageKind, username: &str, auth: TokenAuth, db: DB) -> Status<Json<Value>> {
+    let conn = db.conn();
+    let mut user = auth.user;
+    if !user.is_named(username) {
+        return status!(
+            Forbidden,
+            Json(json!({"error": format!("You may only change your own {}", kind.name())}))
+        );
+    }
+
+    let old_key = user.image_key(kind).cloned();
+    or_return!(user.set_image_key(conn, kind, None), |_| DB_FAILURE!());
+    if let Some(old_key) = old_key {
+        delete_stored(kind, &old_key);
+    }
+    status!(NoContent)
+}
+
+/// View with which a user uploads a new avatar
+///
+/// Avatars are stored as squares, cropped from the middle of the upload.
+#[put("/users/<username>/avatar", data = "<data>")]
+fn put_avatar(
+    username: String,
+    boundary: MultipartBoundary,
+    data: Data,
+    auth: TokenAuth,
+    db: DB,
+) -> Status<Json<Value>> {
+    upload_image(ImageKind::Avatar, &username, boundary, data, auth, db)
+}
+
+/// View with which a user removes their avatar
+#[delete("/users/<username>/avatar")]
+fn delete_avatar(username: String, auth: TokenAuth, db: DB) -> Status<Json<Value>> {
+    remove_image(ImageKind::Avatar, &username, auth, db)
+}
+
+/// View with which a user uploads a new banner
+///
+/// Banners are stored at 3:1, cropped from the middle of the upload.
+#[put("/users/<username>/banner", data = "<data>")]
+fn put_banner(
+    username: String,
+    boundary: MultipartBoundary,
+    data: Data,
+    auth: TokenAuth,
+    db: DB,
+) -> Status<Json<Value>> {
+    upload_image(ImageKind::Banner, &username, boundary, data, auth, db)
+}
+
+/// View with which a user removes their banner
+#[delete("/users/<username>/banner")]
+fn delete_banner(username: String, auth: TokenAuth, db: DB) -> Status<Json<Value>> {
+    remove_image(ImageKind::Banner, &username, auth, db)
+}
+
+/// View which serves the files of the `local` blob store
+///
+/// Mount this at `BLOB_BASE_URL`. Stores which serve their own files don't need it.
+#[get("/<path..>")]
+fn serve_media(path: PathBuf) -> Option<NamedFile> {
+    NamedFile::open(Path::new(&*BLOB_DIR).join(path)).ok()
+}
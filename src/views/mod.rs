@@ -3,11 +3,77 @@
 //! Views are like Django views: they declare the business logic of the application.
 //! However, they also include the routing information.
 
+use auth::token::WWW_AUTHENTICATE;
+use page::Problem as PageProblem;
+use rocket::request::Request;
 use rocket_contrib::{Json, Value};
+use status::{Status, Unauthorized};
 
+macro_rules! DB_FAILURE {
+    () => {
+        status!(
+            InternalServerError,
+            Json(json!({"error": "Failed to connect to backing database"}))
+        )
+    }
+}
+
+macro_rules! or_return {
+    ($predicate:expr, $rv_func:expr) => {
+        match $predicate {
+            Ok(v) => v,
+            Err(e) => return $rv_func(e),
+        }
+    }
+}
+
+pub mod data_export;
+pub use self::data_export::*;
+pub mod follows;
+pub use self::follows::*;
+pub mod images;
+pub use self::images::*;
+pub mod password_reset;
+pub use self::password_reset::*;
+pub mod pings;
+pub use self::pings::*;
+pub mod session;
+pub use self::session::*;
+pub mod two_factor;
+pub use self::two_factor::*;
 pub mod user_account;
 pub use self::user_account::*;
 
+/// The response to a request for a page with unusable `before` or `after` parameters
+pub fn bad_page_query(problem: PageProblem) -> Status<Json<Value>> {
+    status!(
+        BadRequest,
+        Json(json!({
+            "error": problem.message(),
+            "code": problem.code(),
+        }))
+    )
+}
+
+/// Every 401 response must carry a `WWW-Authenticate` header.
+///
+/// `TokenAuth` can only fail with a bare status, so we add the header here.
+#[error(401)]
+fn unauthorized(request: &Request) -> Unauthorized<Json<Value>> {
+    let reason = if request.headers().contains("Authorization") {
+        "Token presented was malformed or has expired"
+    } else {
+        "Authentication required"
+    };
+    Unauthorized(
+        String::from(WWW_AUTHENTICATE),
+        Json(json!({
+            "status": "error",
+            "reason": reason,
+        })),
+    )
+}
+
 #[error(404)]
 fn not_found() -> Json<Value> {
     Json(json!({
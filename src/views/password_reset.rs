@@ -0,0 +1,118 @@
+//! Views which let users reset forgotten passwords.
+//!
+//! Neither of these uses the `TokenAuth` guard: the whole point is that the
+//! user can't sign in. Instead, proof of identity is a single-use token sent
+//! to the user's email address.
+
+use auth::reset::PasswordReset;
+use auth::token::TokenAuth;
+use db::DB;
+use diesel::prelude::*;
+use mail::{normalize_address, MAILER};
+use models::User;
+use rocket_contrib::{Json, Value};
+use status::Status;
+use super::validate_password;
+
+#[derive(Deserialize)]
+struct ResetRequest {
+    pub email: String,
+}
+
+#[derive(Deserialize)]
+struct ResetConfirmation {
+    pub token: String,
+    pub new_password: String,
+}
+
+/// View with which to request a password reset
+///
+/// Emails a reset token to the given address, if it belongs to anyone.
+/// The response is the same whether or not it does, so that this can't
+/// be used to find out who has an account. That includes failing to send
+/// the email, which only ever happens for addresses with an account; such
+/// failures are logged instead.
+#[post("/password_resets", format = "application/json", data = "<request>")]
+fn request_password_reset(request: Json<ResetRequest>, db: DB) -> Status<Json<Value>> {
+    use schema::users::dsl::*;
+
+    let address = normalize_address(&request.email).unwrap_or_default();
+    let user = or_return!(
+        users
+            .filter(email.eq(&address))
+            .first::<User>(db.conn())
+            .optional(),
+        |_| DB_FAILURE!()
+    );
+
+    if let Some(user) = user {
+        let sent = PasswordReset::create_for(&user)
+            .map_err(String::from)
+            .and_then(|key| {
+                let body = format!(
+                    "Someone asked to reset the password for the sonar account @{}.\n\n\
+                     If that was you, use this token to choose a new password:\n\n{}\n\n\
+                     If it wasn't you, you can ignore this email; your password hasn't changed.",
+                    user.username,
+                    key
+                );
+                MAILER.send(&address, "Reset your sonar password", &body)
+            });
+        if let Err(e) = sent {
+            error!("Failed to send a password reset to user {}: {}", user.id, e);
+        }
+    }
+
+    status!(
+        Accepted,
+        Some(Json(json!({
+            "status": "If that address belongs to an account, a reset token is on its way",
+        })))
+    )
+}
+
+/// View with which to set a new password using a reset token
+///
+/// Every session the user holds is revoked; they'll need to sign in again
+/// with the new password.
+#[post("/password_resets/confirm", format = "application/json", data = "<confirmation>")]
+fn confirm_password_reset(confirmation: Json<ResetConfirmation>, db: DB) -> Status<Json<Value>> {
+    let invalid = || {
+        status!(
+            Forbidden,
+            Json(json!({"error": "Reset token was not valid or has expired"}))
+        )
+    };
+
+    // Check the new password before using up the token, so that the user
+    // can try again with a better one.
+    let user = or_return!(PasswordReset::user_for(&confirmation.token), |e| {
+        status!(InternalServerError, Json(json!({ "error": e })))
+    });
+    match user {
+        Some(user) => {
+            or_return!(
+                validate_password(&confirmation.new_password, &user.username),
+                |e| e
+            )
+        }
+        None => return invalid(),
+    }
+
+    let user = or_return!(PasswordReset::redeem(&confirmation.token), |e| {
+        status!(InternalServerError, Json(json!({ "error": e })))
+    });
+    let mut user = match user {
+        Some(user) => user,
+        None => return invalid(),
+    };
+
+    or_return!(
+        user.set_password(db.conn(), &confirmation.new_password),
+        |_| DB_FAILURE!()
+    );
+    or_return!(TokenAuth::invalidate_for(&user), |e| {
+        status!(InternalServerError, Json(json!({ "error": e })))
+    });
+    status!(NoContent)
+}
@@ -0,0 +1,181 @@
+//! Views which create, list, show and delete pings.
+//!
+//! Pings are the short messages users post. Each carries the links found
+//! in it, so that clients can expand them without finding them themselves.
+//!
+//! Deleted pings answer 410 rather than 404, so that clients can tell a
+//! ping which was deleted from one which never existed.
+
+use auth::token::TokenAuth;
+use config::PAGE_SIZE;
+use db::{Connection, DB};
+use links;
+use models::{NewPing, Ping, User};
+use page::{link_header, paginate, PageQuery, WithLinks};
+use ping::check as check_ping;
+use rocket_contrib::{Json, Value};
+use status::Status;
+use super::{bad_page_query, find_visible_user, serialize_user};
+
+#[derive(Deserialize)]
+struct PingData {
+    pub content: String,
+}
+
+fn serialize_ping(ping: &Ping) -> Value {
+    json!({
+        "id": ping.id,
+        "content": ping.content,
+        "links": links::find(&ping.content),
+        "timestamp": ping.timestamp,
+        "likes": ping.likes,
+        "echoes": ping.echoes,
+    })
+}
+
+/// Serialize a ping along with its author's profile
+fn serialize_ping_with_author(
+    conn: &Connection,
+    ping: &Ping,
+    author: &User,
+) -> Result<Json<Value>, Status<Json<Value>>> {
+    let mut ping_data = serialize_ping(ping);
+    ping_data["author"] = serialize_user(conn, author)?.0;
+    Ok(Json(ping_data))
+}
+
+/// View with which a user posts a ping
+#[post("/pings", format = "application/json", data = "<ping_data>")]
+fn create_ping(ping_data: Json<PingData>, auth: TokenAuth, db: DB) -> Status<Json<Value>> {
+    if let Err(problem) = check_ping(&ping_data.content) {
+        return status!(
+            BadRequest,
+            Json(json!({
+                "error": problem.message(),
+                "code": problem.code(),
+            }))
+        );
+    }
+
+    let ping = or_return!(
+        NewPing {
+            user_id: auth.user.id,
+            content: ping_data.content.trim(),
+        }.insert(db.conn()),
+        |_| DB_FAILURE!()
+    );
+    let ping_data = or_return!(
+        serialize_ping_with_author(db.conn(), &ping, &auth.user),
+        |e| e
+    );
+    status!(Created, format!("/pings/{}", ping.id), Some(ping_data))
+}
+
+/// Find a ping which the viewer may see, with its author, or the response explaining why not
+///
+/// As with profiles, pings by deactivated users, or by users who have
+/// blocked or been blocked by the viewer, are reported as not found. That's
+/// settled before a deleted ping is reported as gone, so that the difference
+/// doesn't give away a hidden author's pings.
+fn find_visible_ping(
+    conn: &Connection,
+    id: i32,
+    viewer: Option<&User>,
+) -> Result<(Ping, User), Status<Json<Value>>> {
+    let not_found = || status!(NotFound, Json(json!({"error": "No such ping"})));
+
+    let ping = match Ping::find(conn, id) {
+        Ok(Some(ping)) => ping,
+        Ok(None) => return Err(not_found()),
+        Err(_) => return Err(DB_FAILURE!()),
+    };
+    let author = match ping.author(conn) {
+        Ok(author) => author,
+        Err(_) => return Err(DB_FAILURE!()),
+    };
+    if author.is_deactivated() {
+        return Err(not_found());
+    }
+    if let Some(viewer) = viewer {
+        match viewer.blocks_between(conn, &author) {
+            Ok(false) => {}
+            Ok(true) => return Err(not_found()),
+            Err(_) => return Err(DB_FAILURE!()),
+        }
+    }
+
+    if ping.is_deleted() {
+        return Err(status!(
+            Gone,
+            Json(json!({"error": "This ping has been deleted"}))
+        ));
+    }
+    Ok((ping, author))
+}
+
+/// View with which to get a ping, with its author's profile
+#[get("/pings/<id>")]
+fn get_ping(id: i32, auth: Option<TokenAuth>, db: DB) -> Status<Json<Value>> {
+    let conn = db.conn();
+    let viewer = auth.as_ref().map(|auth| &auth.user);
+    let (ping, author) = or_return!(find_visible_ping(conn, id, viewer), |e| e);
+    status!(
+        Ok,
+        or_return!(serialize_ping_with_author(conn, &ping, &author), |e| e)
+    )
+}
+
+/// View with which a user deletes one of their pings
+#[delete("/pings/<id>")]
+fn delete_ping(id: i32, auth: TokenAuth, db: DB) -> Status<Json<Value>> {
+    let conn = db.conn();
+    let (mut ping, _) = or_return!(find_visible_ping(conn, id, Some(&auth.user)), |e| e);
+    if ping.user_id != auth.user.id {
+        return status!(
+            Forbidden,
+            Json(json!({"error": "You may only delete your own pings"}))
+        );
+    }
+
+    or_return!(ping.delete(conn), |_| DB_FAILURE!());
+    status!(NoContent)
+}
+
+/// View with which to list a user's pings, newest first
+///
+/// The list is paginated by cursor; follow the `Link` header for older
+/// (`rel="next"`) or newer (`rel="prev"`) pings. As with profiles, the
+/// pings of deactivated users, or of users who have blocked or been blocked
+/// by the viewer, are reported as not found.
+#[get("/users/<username>/pings")]
+fn get_user_pings(
+    username: String,
+    page_query: PageQuery,
+    auth: Option<TokenAuth>,
+    db: DB,
+) -> WithLinks<Status<Json<Value>>> {
+    let conn = db.conn();
+    let position = or_return!(page_query.position(), |problem| {
+        WithLinks(None, bad_page_query(problem))
+    });
+    let viewer = auth.as_ref().map(|auth| &auth.user);
+    let user = or_return!(find_visible_user(conn, &username, viewer), |e| {
+        WithLinks(None, e)
+    });
+
+    let fetched = match user.pings_page(conn, position, *PAGE_SIZE + 1) {
+        Ok(fetched) => fetched,
+        Err(_) => return WithLinks(None, DB_FAILURE!()),
+    };
+    let page = paginate(fetched, position, *PAGE_SIZE, Ping::cursor);
+    WithLinks(
+        link_header(&page_query.path, &page),
+        status!(
+            Ok,
+            Json(json!({
+                "username": user.username,
+                "pings": page.items.iter().map(serialize_ping).collect::<Vec<_>>(),
+            }))
+        ),
+    )
+}
@@ -0,0 +1,225 @@
+//! Views which control sessions.
+//!
+//! A session is just an auth token: creating one is how a client signs in.
+//! Each user may hold several sessions at once, one per device, and can
+//! list and revoke them individually.
+
+use auth::throttle::LoginThrottle;
+use auth::token::{TokenAuth, WWW_AUTHENTICATE};
+use auth::two_factor::LoginChallenge;
+use chrono::Duration;
+use db::DB;
+use diesel;
+use diesel::prelude::*;
+use diesel::result::Error::NotFound;
+use models::{Token, User};
+use rocket::outcome::Outcome::*;
+use rocket::request::{Request, FromRequest, Outcome};
+use rocket_contrib::{Json, Value};
+use status::Status;
+
+/// Request guard which extracts the `User-Agent` header, if any
+///
+/// This guard never fails; a missing header simply produces `None`.
+pub struct UserAgent(pub Option<String>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for UserAgent {
+    type Error = ();
+    fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
+        Success(UserAgent(
+            request.headers().get_one("User-Agent").map(String::from),
+        ))
+    }
+}
+
+/// Request guard which extracts the address of the client, if known
+///
+/// This guard never fails either.
+pub struct ClientIp(pub Option<String>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for ClientIp {
+    type Error = ();
+    fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
+        Success(ClientIp(
+            request.remote().map(|address| address.ip().to_string()),
+        ))
+    }
+}
+
+#[derive(Deserialize)]
+struct Credentials {
+    pub username: String,
+    pub password: String,
+    pub device_name: Option<String>,
+}
+
+fn serialize_session(token: &Token, current: &Token) -> Value {
+    json!({
+        "id": token.id,
+        "device_name": token.device_name,
+        "user_agent": token.user_agent,
+        "created": token.timestamp,
+        "last_used": token.last_used,
+        "current": token.id == current.id,
+    })
+}
+
+/// The response to a login attempt refused because of too many failures
+pub fn too_many_failures(wait: Duration) -> Status<Json<Value>> {
+    // Round up, so that clients which wait exactly as long as they're told succeed
+    let seconds = (wait.num_milliseconds() + 999) / 1000;
+    status!(
+        TooManyRequests,
+        seconds as u64,
+        Json(json!({"error": "Too many failed logins; try again later"}))
+    )
+}
+
+/// View with which to log in
+///
+/// Exchanges a valid username and password for a new auth token.
+///
+/// If the user has two-factor authentication enabled, no token is issued yet:
+/// instead, this returns 202 with a challenge, which must be answered with a
+/// code at `/sessions/two_factor`.
+///
+/// Repeated failures from the same address or against the same username
+/// are throttled; throttled attempts get 429 without their credentials
+/// being checked. The failures are only forgiven once a token is issued, so
+/// for users with two-factor authentication, not until the challenge is answered.
+///
+/// Users whose stored password was corrupted, and so must be reset, get 403
+/// with the code `password_reset_required`, whatever password is given: no
+/// password could ever match. Such attempts still count as failures, and
+/// are throttled like any other.
+#[post("/sessions", format = "application/json", data = "<credentials>")]
+fn create_session(
+    credentials: Json<Credentials>,
+    user_agent: UserAgent,
+    client_ip: ClientIp,
+    db: DB,
+) -> Status<Json<Value>> {
+    let conn = db.conn();
+    let ip = client_ip.0.as_ref().map(|ip| ip.as_str());
+    let user_agent = user_agent.0.as_ref().map(|ua| ua.as_str());
+
+    let retry_after = or_return!(
+        LoginThrottle::retry_after(conn, &credentials.username, ip),
+        |e| status!(InternalServerError, Json(json!({ "error": e })))
+    );
+    if let Some(wait) = retry_after {
+        // The audit trail is a nice-to-have; refusing the attempt is what matters.
+        let _ = LoginThrottle::record_refusal(conn, &credentials.username, ip, user_agent);
+        return too_many_failures(wait);
+    }
+
+    let reset_required = or_return!(
+        User::password_reset_required(conn, &credentials.username),
+        |_| DB_FAILURE!()
+    );
+    if reset_required {
+        or_return!(
+            LoginThrottle::record_failure(conn, &credentials.username, ip, user_agent),
+            |e| status!(InternalServerError, Json(json!({ "error": e })))
+        );
+        return status!(
+            Forbidden,
+            Json(json!({
+                "error": "This account's password must be reset before signing in",
+                "code": "password_reset_required",
+            }))
+        );
+    }
+
+    let mut user = match User::get_validated(&conn, &credentials.username, &credentials.password) {
+        Ok(user) => user,
+        Err(NotFound) => {
+            or_return!(
+                LoginThrottle::record_failure(conn, &credentials.username, ip, user_agent),
+                |e| status!(InternalServerError, Json(json!({ "error": e })))
+            );
+            return status!(
+                Unauthorized,
+                String::from(WWW_AUTHENTICATE),
+                Json(json!({"error": "Invalid username or password"}))
+            );
+        }
+        Err(_) => return DB_FAILURE!(),
+    };
+
+    let device_name = credentials.device_name.as_ref().map(|d| d.as_str()).unwrap_or("");
+    if user.two_factor_enabled() {
+        // A deleted account is only reactivated once the login is complete
+        let challenge = or_return!(
+            LoginChallenge::create_for(conn, &user, device_name, user_agent),
+            |e| status!(InternalServerError, Json(json!({ "error": e })))
+        );
+        return status!(Accepted, Some(Json(json!({ "challenge": challenge }))));
+    }
+
+    // Signing in to an account which is due to be deleted cancels the deletion
+    if user.is_deactivated() {
+        or_return!(user.reactivate(&conn), |_| DB_FAILURE!());
+    }
+    or_return!(LoginThrottle::record_success(conn, &user.username), |e| {
+        status!(InternalServerError, Json(json!({ "error": e })))
+    });
+    let key = or_return!(
+        TokenAuth::create_for(&user, device_name, user_agent),
+        |e| status!(InternalServerError, Json(json!({ "error": e })))
+    );
+    status!(Ok, Json(json!({ "token": key })))
+}
+
+/// View with which to list the current user's sessions
+#[get("/sessions")]
+fn list_sessions(auth: TokenAuth, db: DB) -> Status<Json<Value>> {
+    use schema::auth_tokens::dsl::*;
+
+    let tokens = or_return!(
+        auth_tokens
+            .filter(user_id.eq(auth.user.id))
+            .order(last_used.desc())
+            .load::<Token>(db.conn()),
+        |_| DB_FAILURE!()
+    );
+    status!(
+        Ok,
+        Json(Value::Array(
+            tokens.iter().map(|t| serialize_session(t, &auth.token)).collect(),
+        ))
+    )
+}
+
+/// View with which to refresh the current session
+///
+/// The token used to make this request is given a new key, which is returned;
+/// the old key stops working immediately. Refreshing doesn't extend the
+/// session's absolute lifetime; once that's over, the client must sign in again.
+#[post("/sessions/refresh")]
+fn refresh_session(auth: TokenAuth) -> Status<Json<Value>> {
+    let key = or_return!(auth.refresh(), |e| {
+        status!(InternalServerError, Json(json!({ "error": e })))
+    });
+    status!(Ok, Json(json!({ "token": key })))
+}
+
+/// View with which to revoke one of the current user's sessions
+///
+/// Sessions belonging to other users are reported as not found.
+#[delete("/sessions/<session_id>")]
+fn delete_session(session_id: i32, auth: TokenAuth, db: DB) -> Status<Json<Value>> {
+    use schema::auth_tokens::dsl::*;
+
+    let deleted = or_return!(
+        diesel::delete(auth_tokens.filter(id.eq(session_id)).filter(
+            user_id.eq(auth.user.id),
+        )).execute(db.conn()),
+        |_| DB_FAILURE!()
+    );
+    if deleted == 0 {
+        status!(NotFound, Json(json!({"error": "No such session"})))
+    } else {
+        status!(NoContent)
+    }
+}
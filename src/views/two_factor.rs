@@ -0,0 +1,191 @@
+//! Views which control two-factor authentication.
+//!
+//! Enrolling is a two-step process: the user asks for a new secret, adds it
+//! to their authenticator, then confirms a code to switch it on. Once it's
+//! on, signing in returns a challenge which must be answered with a code
+//! before a token is issued.
+
+use auth::throttle::LoginThrottle;
+use auth::token::TokenAuth;
+use auth::two_factor::{Answer, LoginChallenge, TwoFactor};
+use db::DB;
+use rocket_contrib::{Json, Value};
+use status::Status;
+use super::{too_many_failures, ClientIp, UserAgent};
+
+#[derive(Deserialize)]
+struct CodeData {
+    pub code: String,
+}
+
+#[derive(Deserialize)]
+struct DisableData {
+    pub password: String,
+    pub code: String,
+}
+
+#[derive(Deserialize)]
+struct ChallengeAnswer {
+    pub challenge: String,
+    pub code: String,
+}
+
+/// View with which a user starts enrolling in two-factor authentication
+///
+/// Returns the new secret, both for typing in by hand and as an `otpauth://`
+/// URI suitable for display as a QR code. Nothing changes for the user until they confirm it.
+#[post("/users/<username>/totp")]
+fn begin_totp_enrollment(username: String, auth: TokenAuth) -> Status<Json<Value>> {
+    let user = auth.user;
+    if !user.is_named(&username) {
+        return status!(
+            Forbidden,
+            Json(json!({"error": "You may only manage your own two-factor authentication"}))
+        );
+    }
+    if user.two_factor_enabled() {
+        return status!(
+            Conflict,
+            Json(json!({"error": "Two-factor authentication is already enabled"}))
+        );
+    }
+
+    let (secret, uri) = or_return!(TwoFactor::begin_enrollment(&user), |e| {
+        status!(InternalServerError, Json(json!({ "error": e })))
+    });
+    status!(
+        Ok,
+        Json(json!({
+            "secret": secret,
+            "otpauth_uri": uri,
+        }))
+    )
+}
+
+/// View with which a user finishes enrolling in two-factor authentication
+///
+/// Returns the user's recovery codes. These are shown exactly once.
+#[post("/users/<username>/totp/confirm", format = "application/json", data = "<confirmation>")]
+fn confirm_totp_enrollment(
+    username: String,
+    confirmation: Json<CodeData>,
+    auth: TokenAuth,
+) -> Status<Json<Value>> {
+    let user = auth.user;
+    if !user.is_named(&username) {
+        return status!(
+            Forbidden,
+            Json(json!({"error": "You may only manage your own two-factor authentication"}))
+        );
+    }
+    if user.two_factor_enabled() {
+        return status!(
+            Conflict,
+            Json(json!({"error": "Two-factor authentication is already enabled"}))
+        );
+    }
+    if user.totp_secret.is_none() {
+        return status!(
+            BadRequest,
+            Json(json!({"error": "Start enrolling before confirming a code"}))
+        );
+    }
+
+    let codes = or_return!(
+        TwoFactor::confirm_enrollment(&user, &confirmation.code),
+        |e| status!(InternalServerError, Json(json!({ "error": e })))
+    );
+    match codes {
+        Some(codes) => status!(Ok, Json(json!({ "recovery_codes": codes }))),
+        None => status!(Forbidden, Json(json!({"error": "Code was not valid"}))),
+    }
+}
+
+/// View with which a user turns off two-factor authentication
+///
+/// Requires both the user's password and a current code, so that a stolen
+/// token alone isn't enough to strip the second factor.
+#[delete("/users/<username>/totp", format = "application/json", data = "<disable>")]
+fn disable_totp(
+    username: String,
+    disable: Json<DisableData>,
+    auth: TokenAuth,
+) -> Status<Json<Value>> {
+    let user = auth.user;
+    if !user.is_named(&username) {
+        return status!(
+            Forbidden,
+            Json(json!({"error": "You may only manage your own two-factor authentication"}))
+        );
+    }
+    if !user.two_factor_enabled() {
+        return status!(
+            Conflict,
+            Json(json!({"error": "Two-factor authentication is not enabled"}))
+        );
+    }
+    if !user.check_password(&disable.password) {
+        return status!(Forbidden, Json(json!({"error": "Password is incorrect"})));
+    }
+    let valid = or_return!(TwoFactor::check(&user, &disable.code), |e| {
+        status!(InternalServerError, Json(json!({ "error": e })))
+    });
+    if !valid {
+        return status!(Forbidden, Json(json!({"error": "Code was not valid"})));
+    }
+
+    or_return!(TwoFactor::disable(&user), |e| {
+        status!(InternalServerError, Json(json!({ "error": e })))
+    });
+    status!(NoContent)
+}
+
+/// View with which to finish logging in with two-factor authentication
+///
+/// Exchanges the challenge returned by `create_session`, and a code from the
+/// user's authenticator or one of their recovery codes, for a new auth token.
+///
+/// Wrong codes are failed logins, throttled just like wrong passwords.
+#[post("/sessions/two_factor", format = "application/json", data = "<answer>")]
+fn answer_login_challenge(
+    answer: Json<ChallengeAnswer>,
+    user_agent: UserAgent,
+    client_ip: ClientIp,
+    db: DB,
+) -> Status<Json<Value>> {
+    let conn = db.conn();
+    let ip = client_ip.0.as_ref().map(|ip| ip.as_str());
+    let user_agent = user_agent.0.as_ref().map(|ua| ua.as_str());
+
+    let answered = or_return!(
+        LoginChallenge::answer(conn, &answer.challenge, &answer.code, ip, user_agent),
+        |e| status!(InternalServerError, Json(json!({ "error": e })))
+    );
+    let (mut user, challenge) = match answered {
+        Answer::Accepted(user, challenge) => (user, challenge),
+        Answer::Rejected => {
+            return status!(
+                Forbidden,
+                Json(json!({"error": "Challenge or code was not valid, or the challenge has expired"}))
+            )
+        }
+        Answer::Throttled(wait) => return too_many_failures(wait),
+    };
+
+    // Signing in to an account which is due to be deleted cancels the deletion
+    if user.is_deactivated() {
+        or_return!(user.reactivate(conn), |_| DB_FAILURE!());
+    }
+    or_return!(LoginThrottle::record_success(conn, &user.username), |e| {
+        status!(InternalServerError, Json(json!({ "error": e })))
+    });
+    let key = or_return!(
+        TokenAuth::create_for(
+            &user,
+            &challenge.device_name,
+            challenge.user_agent.as_ref().map(|ua| ua.as_str()),
+        ),
+        |e| status!(InternalServerError, Json(json!({ "error": e })))
+    );
+    status!(Ok, Json(json!({ "token": key })))
+}
@@ -5,30 +5,31 @@
 //! not all use the `TokenAuth` guard; after all, you have
 //! to get your token from somewhere.
 
+use auth::policy::Policy;
+use auth::throttle::LoginThrottle;
+use auth::token::TokenAuth;
+use auth::verification::EmailVerification;
+use config::ACCOUNT_DELETION_GRACE_PERIOD;
 use db::{Connection, DB};
+use diesel;
+use diesel::Connection as ConnectionTrait;
 use diesel::prelude::*;
 use diesel::select;
-use models::{NewUser, User};
+use links;
+use mail::{normalize_address, MAILER};
+use media::ImageKind;
+use models::{NewUser, ProfileField, User};
+use ring::digest;
+use rocket::http::Status as HttpStatus;
+use rocket::http::hyper::header::Location;
+use rocket::outcome::Outcome::*;
+use rocket::request::{Request, FromRequest, Outcome};
+use rocket::response::{Response, Responder};
 use rocket_contrib::{Json, Value};
+use schema::users;
 use status::Status;
-
-macro_rules! DB_FAILURE {
-    () => {
-        status!(
-            InternalServerError,
-            Json(json!({"error": "Failed to connect to backing database"}))
-        )
-    }
-}
-
-macro_rules! or_return {
-    ($predicate:expr, $rv_func:expr) => {
-        match $predicate {
-            Ok(v) => v,
-            Err(e) => return $rv_func(e),
-        }
-    }
-}
+use super::{image_urls, too_many_failures, ClientIp, UserAgent};
+use username::check as check_username;
 
 #[derive(Deserialize)]
 struct UserData {
@@ -36,6 +37,10 @@ struct UserData {
     pub password: String,
     pub real_name: Option<String>,
     pub blurb: Option<String>,
+    pub email: Option<String>,
+    pub location: Option<String>,
+    pub website: Option<String>,
+    pub pronouns: Option<String>,
 }
 
 impl UserData {
@@ -47,9 +52,17 @@ impl UserData {
         use diesel::expression::dsl::exists;
         use schema::users::dsl::*;
 
-        let username_already_exists: bool = select(
-            exists(users.filter(username.eq(&self.username))),
-        ).get_result(conn)
+        if let Err(problem) = check_username(&self.username) {
+            return Err(status!(
+                BadRequest,
+                Json(json!({
+                    "error": problem.message(),
+                    "code": problem.code(),
+                }))
+            ));
+        }
+
+        let username_already_exists = User::username_taken(conn, &self.username, None)
             .map_err(|_| DB_FAILURE!())?;
 
         if username_already_exists {
@@ -59,36 +72,334 @@ impl UserData {
             ));
         }
 
-        if self.password.len() < 16 {
-            return Err(status!(
-                BadRequest,
-                Json(json!({"error": "Password too short"}))
-            ));
+        if let Some(ref address) = self.email {
+            let email_already_exists: bool = select(exists(users.filter(email.eq(address))))
+                .get_result(conn)
+                .map_err(|_| DB_FAILURE!())?;
+            if email_already_exists {
+                return Err(status!(
+                    BadRequest,
+                    Json(json!({"error": "Email address already in use"}))
+                ));
+            }
         }
 
-        Ok(())
+        validate_profile(
+            self.real_name.as_ref().map(|r| r.as_str()).unwrap_or(""),
+            self.blurb.as_ref().map(|b| b.as_str()).unwrap_or(""),
+        )?;
+        validate_password(&self.password, &self.username)
     }
 
-    fn into_user(self, conn: &Connection) -> Result<User, Status<Json<Value>>> {
-        let new_user = self.validate(conn).map(move |_| {
-            NewUser::new(
-                self.username,
-                self.password,
-                self.real_name.unwrap_or(String::new()),
-                self.blurb.unwrap_or(String::new()),
-            )
-        })?;
+    fn into_user(mut self, conn: &Connection) -> Result<User, Status<Json<Value>>> {
+        if let Some(address) = self.email.take() {
+            self.email = Some(normalize_address(&address).ok_or_else(|| {
+                status!(
+                    BadRequest,
+                    Json(json!({"error": "Email address is not valid"}))
+                )
+            })?);
+        }
+
+        self.validate(conn)?;
+        let location = self.location.take().unwrap_or(String::new());
+        let pronouns = self.pronouns.take().unwrap_or(String::new());
+        let website = validate_details(
+            &location,
+            self.website.as_ref().map(|w| w.as_str()).unwrap_or(""),
+            &pronouns,
+        )?;
+
+        let new_user = NewUser::new(
+            self.username,
+            self.password,
+            self.real_name.unwrap_or(String::new()),
+            self.blurb.unwrap_or(String::new()),
+            self.email,
+        ).with_details(location, website, pronouns);
         new_user.insert(conn).map_err(|_| DB_FAILURE!())
     }
 }
 
+/// Longest permissible real name, in characters
+const REAL_NAME_MAX_LENGTH: usize = 50;
+/// Longest permissible blurb, in characters
+const BLURB_MAX_LENGTH: usize = 160;
+
+/// Longest permissible location, in characters
+const LOCATION_MAX_LENGTH: usize = 30;
+/// Longest permissible website URL, in characters
+const WEBSITE_MAX_LENGTH: usize = 100;
+/// Longest permissible pronouns, in characters
+const PRONOUNS_MAX_LENGTH: usize = 30;
+/// Most custom fields a profile may have
+const PROFILE_FIELDS_MAX: usize = 4;
+/// Longest permissible custom field label, in characters
+const FIELD_LABEL_MAX_LENGTH: usize = 30;
+/// Longest permissible custom field value, in characters
+const FIELD_VALUE_MAX_LENGTH: usize = 100;
+
+fn invalid_profile(error: String) -> Status<Json<Value>> {
+    status!(BadRequest, Json(json!({ "error": error })))
+}
+
+/// Check one piece of profile text against a length limit
+///
+/// Control characters are never allowed, except newlines in `multiline` text.
+fn check_profile_text(
+    what: &str,
+    text: &str,
+    max_length: usize,
+    multiline: bool,
+) -> Result<(), Status<Json<Value>>> {
+    if text.chars().count() > max_length {
+        return Err(invalid_profile(
+            format!("{} must be at most {} characters long", what, max_length),
+        ));
+    }
+    if text.chars().any(|c| c.is_control() && !(multiline && c == '\n')) {
+        return Err(invalid_profile(
+            format!("{} must not contain control characters", what),
+        ));
+    }
+    Ok(())
+}
+
+/// Check whether proposed profile text is acceptable.
+///
+/// Return Err(Json) with an explanation if not.
+fn validate_profile(real_name: &str, blurb: &str) -> Result<(), Status<Json<Value>>> {
+    check_profile_text("Real name", real_name, REAL_NAME_MAX_LENGTH, false)?;
+    // Blurbs may span several lines, but nothing else invisible is allowed
+    check_profile_text("Blurb", blurb, BLURB_MAX_LENGTH, true)
+}
+
+/// Check whether a proposed location, website and pronouns are acceptable.
+///
+/// Return Ok(String) with the website normalized if so; an empty website
+/// stays empty. Return Err(Json) with an explanation if not.
+fn validate_details(
+    location: &str,
+    website: &str,
+    pronouns: &str,
+) -> Result<String, Status<Json<Value>>> {
+    check_profile_text("Location", location, LOCATION_MAX_LENGTH, false)?;
+    check_profile_text("Pronouns", pronouns, PRONOUNS_MAX_LENGTH, false)?;
+
+    let website = website.trim();
+    if website.is_empty() {
+        return Ok(String::new());
+    }
+    let website = links::normalize(website).map_err(|problem| {
+        status!(
+            BadRequest,
+            Json(json!({
+                "error": format!("Website is not valid: {}", problem.message()),
+                "code": problem.code(),
+            }))
+        )
+    })?;
+    check_profile_text("Website", &website, WEBSITE_MAX_LENGTH, false)?;
+    Ok(website)
+}
+
+/// Check whether proposed custom profile fields are acceptable.
+///
+/// Return Ok(Vec) of their labels and values, trimmed, if so.
+/// Return Err(Json) with an explanation if not.
+fn validate_fields(fields: &[FieldData]) -> Result<Vec<(String, String)>, Status<Json<Value>>> {
+    if fields.len() > PROFILE_FIELDS_MAX {
+        return Err(invalid_profile(
+            format!("A profile may have at most {} fields", PROFILE_FIELDS_MAX),
+        ));
+    }
+    fields
+        .iter()
+        .map(|field| {
+            let label = field.label.trim();
+            let value = field.value.trim();
+            if label.is_empty() {
+                return Err(invalid_profile(String::from("Field labels must not be empty")));
+            }
+            check_profile_text("Field label", label, FIELD_LABEL_MAX_LENGTH, false)?;
+            check_profile_text("Field value", value, FIELD_VALUE_MAX_LENGTH, false)?;
+            Ok((label.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+/// Check whether a proposed password is acceptable for the given user.
+///
+/// Return Err(Json) with an explanation if not. The explanation lists
+/// every rule the password breaks, each with a machine-readable code.
+pub fn validate_password(password: &str, username: &str) -> Result<(), Status<Json<Value>>> {
+    Policy::current().check(password, username).map_err(|violations| {
+        status!(
+            BadRequest,
+            Json(json!({
+                "error": "Password does not meet the password policy",
+                "violations": violations.iter().map(|v| json!({
+                    "code": v.code(),
+                    "message": v.message(),
+                })).collect::<Vec<_>>(),
+            }))
+        )
+    })
+}
+
+/// Email the user a token with which to verify their email address
+fn send_verification_email(user: &User) -> Result<(), &'static str> {
+    let address = user.email.as_ref().ok_or("User has no email address")?;
+    let key = EmailVerification::create_for(user)?;
+    let body = format!(
+        "Welcome to sonar, @{}!\n\n\
+         To confirm that this is your email address, use this token:\n\n{}\n\n\
+         If you didn't sign up for sonar, you can ignore this email.",
+        user.username,
+        key
+    );
+    MAILER
+        .send(address, "Verify your sonar email address", &body)
+        .map_err(|_| "Failed to send verification email")
+}
+
+#[derive(Deserialize)]
+struct EmailVerificationData {
+    pub token: String,
+}
+
+#[derive(Deserialize)]
+struct PasswordChange {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(Deserialize)]
+struct AccountDeletion {
+    pub password: String,
+}
+
+#[derive(Deserialize)]
+struct UsernameChange {
+    pub username: String,
+}
+
+/// A custom label/value pair on a user's profile
+#[derive(Deserialize)]
+struct FieldData {
+    pub label: String,
+    pub value: String,
+}
+
+/// A partial update to a user's profile; fields which are absent are left alone
+///
+/// `fields`, if present, replaces all of the user's custom fields.
+#[derive(Deserialize)]
+struct ProfileUpdate {
+    pub real_name: Option<String>,
+    pub blurb: Option<String>,
+    pub location: Option<String>,
+    pub website: Option<String>,
+    pub pronouns: Option<String>,
+    pub fields: Option<Vec<FieldData>>,
+}
+
+/// Request guard which extracts the `If-Match` header, if any
+///
+/// This guard never fails; a missing header simply produces `None`.
+struct IfMatch(Option<String>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for IfMatch {
+    type Error = ();
+    fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
+        Success(IfMatch(
+            request.headers().get_one("If-Match").map(String::from),
+        ))
+    }
+}
+
+impl IfMatch {
+    /// Whether the precondition holds for a resource with the given ETag
+    ///
+    /// A request without `If-Match` has no precondition, so it always holds.
+    fn matches(&self, etag: &str) -> bool {
+        match self.0 {
+            None => true,
+            Some(ref header) => {
+                header.split(',').map(|tag| tag.trim()).any(
+                    |tag| tag == "*" || tag == etag,
+                )
+            }
+        }
+    }
+}
+
+/// Responder which adds an `ETag` header, if there is one, to the wrapped response
+struct WithETag<R>(Option<String>, R);
 
-fn serialize_user(user: User) -> Json<Value> {
-    Json(json!({
+impl<'r, R: Responder<'r>> Responder<'r> for WithETag<R> {
+    fn respond_to(self, req: &Request) -> Result<Response<'r>, HttpStatus> {
+        let mut response = Response::build_from(self.1.respond_to(req)?);
+        if let Some(etag) = self.0 {
+            response.raw_header("ETag", etag);
+        }
+        response.ok()
+    }
+}
+
+/// Serialize the parts of a user's profile which the user controls
+///
+/// The website and custom field values come with the links found in them,
+/// so that clients needn't find links themselves.
+pub fn serialize_user(conn: &Connection, user: &User) -> Result<Json<Value>, Status<Json<Value>>> {
+    let fields = user.profile_fields(conn).map_err(|_| DB_FAILURE!())?;
+    Ok(Json(json!({
         "username": user.username,
         "real_name": user.real_name,
         "blurb": user.blurb,
-    }))
+        "location": user.location,
+        "website": user.website,
+        "website_links": links::find(&user.website),
+        "pronouns": user.pronouns,
+        "fields": fields.iter().map(|field| json!({
+            "label": field.label,
+            "value": field.value,
+            "links": links::find(&field.value),
+        })).collect::<Vec<_>>(),
+        "avatar": image_urls(ImageKind::Avatar, user.avatar_key.as_ref()),
+        "banner": image_urls(ImageKind::Banner, user.banner_key.as_ref()),
+    })))
+}
+
+/// The ETag of a user's profile, given what `serialize_user` returns for it
+///
+/// This covers everything the user can edit, but not the counts, which
+/// change without the user's say.
+fn profile_etag(user_data: &Value) -> String {
+    let digest = digest::digest(&digest::SHA256, user_data.to_string().as_bytes());
+    let hex: String = digest.as_ref()[..16]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("\"{}\"", hex)
+}
+
+/// Serialize a user's full public profile, counts and all, along with its ETag
+fn serialize_profile(
+    conn: &Connection,
+    user: &User,
+) -> Result<(Json<Value>, String), Status<Json<Value>>> {
+    let mut profile = serialize_user(conn, user)?.0;
+    let etag = profile_etag(&profile);
+
+    let followers = user.follower_count(conn).map_err(|_| DB_FAILURE!())?;
+    let following = user.following_count(conn).map_err(|_| DB_FAILURE!())?;
+    let pings = user.ping_count(conn).map_err(|_| DB_FAILURE!())?;
+    profile["followers"] = json!(followers);
+    profile["following"] = json!(following);
+    profile["pings"] = json!(pings);
+    profile["joined"] = json!(user.joined_at);
+    Ok((Json(profile), etag))
 }
 
 
@@ -98,16 +409,437 @@ fn create_user(user_data: Json<UserData>, db: DB) -> Status<Json<Value>> {
     let conn = db.conn();
     // https://stackoverflow.com/questions/46905070/
     let user = or_return!(user_data.into_inner().into_user(&conn), |e| e);
+    if user.email.is_some() {
+        // The account exists either way; if this fails, the user can ask
+        // for another verification email later.
+        let _ = send_verification_email(&user);
+    }
+    let user_data = or_return!(serialize_user(conn, &user), |e| e);
     status!(
         Created,
         format!("/users/{}", user.username),
-        Some(serialize_user(user))
+        Some(user_data)
     )
 }
 
+/// Find a user whom the viewer, if signed in, may see
+///
+/// Users who are deactivated, or who have blocked or been blocked by the
+/// viewer, are reported as not found, just like users who don't exist.
+pub fn find_visible_user(
+    conn: &Connection,
+    username: &str,
+    viewer: Option<&User>,
+) -> Result<User, Status<Json<Value>>> {
+    let not_found = || status!(NotFound, Json(json!({"error": "No such user"})));
+    let user = match User::find_by_username(conn, username) {
+        Ok(Some(user)) => user,
+        Ok(None) => return Err(not_found()),
+        Err(_) => return Err(DB_FAILURE!()),
+    };
+    if user.is_deactivated() {
+        return Err(not_found());
+    }
+    if let Some(viewer) = viewer {
+        match viewer.blocks_between(conn, &user) {
+            Ok(false) => {}
+            Ok(true) => return Err(not_found()),
+            Err(_) => return Err(DB_FAILURE!()),
+        }
+    }
+    Ok(user)
+}
+
 /// View with which to get a user
+///
+/// Anyone may view a profile, signed in or not, unless the viewer and the
+/// profile's owner have blocked each other; then the profile is reported
+/// as not found.
 #[get("/users/<username>")]
-fn get_user(username: String, db: DB) -> Status<Json<Value>> {
+fn get_user(
+    username: String,
+    auth: Option<TokenAuth>,
+    db: DB,
+) -> WithETag<Status<Json<Value>>> {
     let conn = db.conn();
-    unimplemented!()
+    let viewer = auth.as_ref().map(|auth| &auth.user);
+    let missing = match find_visible_user(conn, &username, viewer) {
+        Ok(user) => {
+            return match serialize_profile(conn, &user) {
+                Ok((profile, etag)) => WithETag(Some(etag), status!(Ok, profile)),
+                Err(e) => WithETag(None, e),
+            }
+        }
+        Err(missing) => missing,
+    };
+
+    // Users who have recently changed their username can still be found by
+    // the old one, but are redirected to the new, if they may be seen at all.
+    let user = match User::find_by_former_username(conn, &username) {
+        Ok(Some(user)) => user,
+        Ok(None) => return WithETag(None, missing),
+        Err(_) => return WithETag(None, DB_FAILURE!()),
+    };
+    let user = or_return!(find_visible_user(conn, &user.username, viewer), |e| {
+        WithETag(None, e)
+    });
+    WithETag(
+        None,
+        status!(
+            MovedPermanently,
+            Location(format!("/users/{}", user.username)),
+            Json(json!({
+                "error": "This user has changed their username",
+                "username": user.username,
+            }))
+        ),
+    )
+}
+
+/// View with which a user edits their profile
+///
+/// Only the fields present in the body are changed. Send the profile's ETag
+/// in `If-Match` to make sure nobody else has changed it in the meantime;
+/// if they have, this returns 412 and changes nothing.
+#[patch("/users/<username>", format = "application/json", data = "<update>")]
+fn update_user(
+    username: String,
+    update: Json<ProfileUpdate>,
+    if_match: IfMatch,
+    auth: TokenAuth,
+    db: DB,
+) -> WithETag<Status<Json<Value>>> {
+    let conn = db.conn();
+    let user = auth.user;
+    if !user.is_named(&username) {
+        return WithETag(
+            None,
+            status!(
+                Forbidden,
+                Json(json!({"error": "You may only edit your own profile"}))
+            ),
+        );
+    }
+    let precondition_failed = || {
+        WithETag(
+            None,
+            status!(
+                PreconditionFailed,
+                Json(json!({"error": "Profile has changed since you last fetched it"}))
+            ),
+        )
+    };
+    let current = match serialize_user(conn, &user) {
+        Ok(current) => current,
+        Err(e) => return WithETag(None, e),
+    };
+    if !if_match.matches(&profile_etag(&current.0)) {
+        return precondition_failed();
+    }
+
+    let update = update.into_inner();
+    let new_real_name = update.real_name.unwrap_or_else(|| user.real_name.clone());
+    let new_blurb = update.blurb.unwrap_or_else(|| user.blurb.clone());
+    let new_location = update.location.unwrap_or_else(|| user.location.clone());
+    let new_pronouns = update.pronouns.unwrap_or_else(|| user.pronouns.clone());
+    if let Err(e) = validate_profile(&new_real_name, &new_blurb) {
+        return WithETag(None, e);
+    }
+    let new_website = match validate_details(
+        &new_location,
+        update.website.as_ref().unwrap_or(&user.website),
+        &new_pronouns,
+    ) {
+        Ok(new_website) => new_website,
+        Err(e) => return WithETag(None, e),
+    };
+    let new_fields = match update.fields {
+        Some(ref fields) => {
+            match validate_fields(fields) {
+                Ok(fields) => Some(fields),
+                Err(e) => return WithETag(None, e),
+            }
+        }
+        None => None,
+    };
+    let field_pairs = |fields: Vec<ProfileField>| -> Vec<(String, String)> {
+        fields.into_iter().map(|f| (f.label, f.value)).collect()
+    };
+    let old_fields = match user.profile_fields(conn) {
+        Ok(fields) => field_pairs(fields),
+        Err(_) => return WithETag(None, DB_FAILURE!()),
+    };
+
+    // Only update the row if it's still as we found it, so that a concurrent
+    // edit which lands between the check above and here is still caught.
+    // Custom fields live in their own table, so check those separately.
+    let result = conn.transaction::<_, diesel::result::Error, _>(|| {
+        let updated = diesel::update(
+            users::table
+                .filter(users::id.eq(user.id))
+                .filter(users::real_name.eq(&user.real_name))
+                .filter(users::blurb.eq(&user.blurb))
+                .filter(users::location.eq(&user.location))
+                .filter(users::website.eq(&user.website))
+                .filter(users::pronouns.eq(&user.pronouns)),
+        ).set((
+            users::real_name.eq(&new_real_name),
+            users::blurb.eq(&new_blurb),
+            users::location.eq(&new_location),
+            users::website.eq(&new_website),
+            users::pronouns.eq(&new_pronouns),
+        ))
+            .execute(conn)?;
+        if updated == 0 || field_pairs(user.profile_fields(conn)?) != old_fields {
+            return Err(diesel::result::Error::RollbackTransaction);
+        }
+        if let Some(ref fields) = new_fields {
+            user.set_profile_fields(conn, fields)?;
+        }
+        Ok(())
+    });
+    match result {
+        Ok(()) => {}
+        Err(diesel::result::Error::RollbackTransaction) => return precondition_failed(),
+        Err(_) => return WithETag(None, DB_FAILURE!()),
+    }
+
+    let user = match users::table.find(user.id).first::<User>(conn) {
+        Ok(user) => user,
+        Err(_) => return WithETag(None, DB_FAILURE!()),
+    };
+    match serialize_profile(conn, &user) {
+        Ok((profile, etag)) => WithETag(Some(etag), status!(Ok, profile)),
+        Err(e) => WithETag(None, e),
+    }
+}
+
+/// View with which a user deletes their account
+///
+/// The account is hidden and every session revoked at once, but nothing is
+/// removed until `ACCOUNT_DELETION_GRACE_PERIOD` has passed; signing in
+/// before then cancels the deletion. See `purge`.
+#[delete("/users/<username>", format = "application/json", data = "<deletion>")]
+fn delete_user(
+    username: String,
+    deletion: Json<AccountDeletion>,
+    auth: TokenAuth,
+    db: DB,
+) -> Status<Json<Value>> {
+    let conn = db.conn();
+    let mut user = auth.user;
+    if !user.is_named(&username) {
+        return status!(
+            Forbidden,
+            Json(json!({"error": "You may only delete your own account"}))
+        );
+    }
+    if !user.check_password(&deletion.password) {
+        return status!(
+            Forbidden,
+            Json(json!({"error": "Password is incorrect"}))
+        );
+    }
+
+    or_return!(user.deactivate(conn), |_| DB_FAILURE!());
+    or_return!(TokenAuth::invalidate_for(&user), |e| {
+        status!(InternalServerError, Json(json!({ "error": e })))
+    });
+    let purge_after = user.deactivated_at.map(|at| at + *ACCOUNT_DELETION_GRACE_PERIOD);
+    status!(Accepted, Some(Json(json!({ "purge_after": purge_after }))))
+}
+
+/// View with which a user changes their username
+///
+/// For `USERNAME_GRACE_PERIOD` afterwards, the old username redirects to the
+/// new one, and nobody else can take it.
+#[put("/users/<username>/username", format = "application/json", data = "<change>")]
+fn change_username(
+    username: String,
+    change: Json<UsernameChange>,
+    auth: TokenAuth,
+    db: DB,
+) -> Status<Json<Value>> {
+    let conn = db.conn();
+    let mut user = auth.user;
+    if !user.is_named(&username) {
+        return status!(
+            Forbidden,
+            Json(json!({"error": "You may only change your own username"}))
+        );
+    }
+    if let Err(problem) = check_username(&change.username) {
+        return status!(
+            BadRequest,
+            Json(json!({
+                "error": problem.message(),
+                "code": problem.code(),
+            }))
+        );
+    }
+    if or_return!(
+        User::username_taken(conn, &change.username, Some(user.id)),
+        |_| DB_FAILURE!()
+    )
+    {
+        return status!(
+            Conflict,
+            Json(json!({"error": "Username already in use; pick another"}))
+        );
+    }
+
+    or_return!(user.rename(conn, &change.username), |_| DB_FAILURE!());
+    status!(Ok, or_return!(serialize_user(conn, &user), |e| e))
+}
+
+/// View with which a user changes their password
+///
+/// Requires the current password as well as a valid token. Every other
+/// session the user holds is revoked; the one used to make this request
+/// stays signed in.
+///
+/// A wrong current password is a failed login, throttled just as it is
+/// when signing in, so that a stolen token can't be used to guess it.
+#[put("/users/<username>/password", format = "application/json", data = "<change>")]
+fn change_password(
+    username: String,
+    change: Json<PasswordChange>,
+    auth: TokenAuth,
+    user_agent: UserAgent,
+    client_ip: ClientIp,
+    db: DB,
+) -> Status<Json<Value>> {
+    let conn = db.conn();
+    let ip = client_ip.0.as_ref().map(|ip| ip.as_str());
+    let user_agent = user_agent.0.as_ref().map(|ua| ua.as_str());
+    let mut user = auth.user;
+    if !user.is_named(&username) {
+        return status!(
+            Forbidden,
+            Json(json!({"error": "You may only change your own password"}))
+        );
+    }
+
+    let retry_after = or_return!(
+        LoginThrottle::retry_after(conn, &user.username, ip),
+        |e| status!(InternalServerError, Json(json!({ "error": e })))
+    );
+    if let Some(wait) = retry_after {
+        // The audit trail is a nice-to-have; refusing the attempt is what matters.
+        let _ = LoginThrottle::record_refusal(conn, &user.username, ip, user_agent);
+        return too_many_failures(wait);
+    }
+    if !user.check_password(&change.current_password) {
+        or_return!(
+            LoginThrottle::record_failure(conn, &user.username, ip, user_agent),
+            |e| status!(InternalServerError, Json(json!({ "error": e })))
+        );
+        return status!(
+            Forbidden,
+            Json(json!({"error": "Current password is incorrect"}))
+        );
+    }
+    or_return!(LoginThrottle::record_success(conn, &user.username), |e| {
+        status!(InternalServerError, Json(json!({ "error": e })))
+    });
+    or_return!(validate_password(&change.new_password, &user.username), |e| e);
+
+    or_return!(user.set_password(&conn, &change.new_password), |_| {
+        DB_FAILURE!()
+    });
+    or_return!(TokenAuth::invalidate_others(&auth.token), |e| {
+        status!(InternalServerError, Json(json!({ "error": e })))
+    });
+    status!(NoContent)
+}
+
+/// View with which a user asks for another email verification token
+#[post("/users/<username>/email/verification")]
+fn resend_email_verification(username: String, auth: TokenAuth) -> Status<Json<Value>> {
+    let user = auth.user;
+    if !user.is_named(&username) {
+        return status!(
+            Forbidden,
+            Json(json!({"error": "You may only verify your own email address"}))
+        );
+    }
+    if user.email.is_none() {
+        return status!(
+            BadRequest,
+            Json(json!({"error": "You have no email address to verify"}))
+        );
+    }
+    if user.email_verified_at.is_some() {
+        return status!(
+            Conflict,
+            Json(json!({"error": "Your email address is already verified"}))
+        );
+    }
+
+    or_return!(send_verification_email(&user), |e| {
+        status!(InternalServerError, Json(json!({ "error": e })))
+    });
+    status!(Accepted, None)
+}
+
+/// View with which a user proves that their email address is theirs
+///
+/// This doesn't need the `TokenAuth` guard: the token which was emailed to
+/// the user is proof enough, and it's likely to be opened on a device where
+/// the user isn't signed in.
+#[post("/users/<username>/email/verify", format = "application/json", data = "<verification>")]
+fn verify_email(
+    username: String,
+    verification: Json<EmailVerificationData>,
+    db: DB,
+) -> Status<Json<Value>> {
+    let user = or_return!(User::find_by_username(db.conn(), &username), |_| {
+        DB_FAILURE!()
+    });
+    let invalid = || {
+        status!(
+            Forbidden,
+            Json(json!({"error": "Verification token was not valid or has expired"}))
+        )
+    };
+    let user = match user {
+        Some(user) => user,
+        None => return invalid(),
+    };
+
+    let verified = or_return!(EmailVerification::redeem(&user, &verification.token), |e| {
+        status!(InternalServerError, Json(json!({ "error": e })))
+    });
+    if verified {
+        status!(NoContent)
+    } else {
+        invalid()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_if_match_absent() {
+        assert!(IfMatch(None).matches("\"abc\""));
+    }
+
+    #[test]
+    fn test_if_match() {
+        let etag = profile_etag(&json!({"username": "sonar"}));
+        assert!(IfMatch(Some(etag.clone())).matches(&etag));
+        assert!(IfMatch(Some(String::from("*"))).matches(&etag));
+        assert!(IfMatch(Some(format!("\"stale\", {}", etag))).matches(&etag));
+        assert!(!IfMatch(Some(String::from("\"stale\""))).matches(&etag));
+    }
+
+    #[test]
+    fn test_etag_follows_content() {
+        let etag = profile_etag(&json!({"username": "sonar", "blurb": ""}));
+        assert!(etag.starts_with('"') && etag.ends_with('"'));
+        assert_eq!(etag, profile_etag(&json!({"username": "sonar", "blurb": ""})));
+        assert_ne!(etag, profile_etag(&json!({"username": "sonar", "blurb": "hi"})));
+    }
 }